@@ -4,7 +4,7 @@ use investec::{client::Client, response::TransactionType};
 #[tokio::main]
 async fn main() -> Result<(), investec::Error> {
     // load credentials from env
-    let mut client = Client::from_env()
+    let client = Client::from_env()
         // save a copy of the token locally as a cache
         .local_token()
         // automatically refresh tokens if expired or non-existent
@@ -12,7 +12,7 @@ async fn main() -> Result<(), investec::Error> {
         .build()?;
 
     // for a client pointing to the sandbox environment
-    // let mut client = Client::sandbox();
+    // let client = Client::sandbox();
 
     // uncomment below if not using .refresh_auto()
     // client.authenticate().await?;
@@ -33,7 +33,7 @@ async fn main() -> Result<(), investec::Error> {
         println!("{:#?}\n", balance.data);
 
         let transactions = client
-            .get_account_transactions(&a.account_id, from_date, to_date, Some(t_type))
+            .get_account_transactions(&a.account_id, from_date, to_date, Some(t_type.clone()))
             .await?;
 
         for t in transactions.data.transactions {
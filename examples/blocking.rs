@@ -0,0 +1,33 @@
+use chrono::NaiveDate;
+use investec::{blocking::Client, response::TransactionType};
+
+// run with: cargo run --example blocking --features blocking
+fn main() -> Result<(), investec::Error> {
+    // load credentials from env
+    let client = Client::from_env()
+        // save a copy of the token locally as a cache
+        .local_token()
+        // automatically refresh tokens if expired or non-existent
+        .refresh_auth()
+        .build_blocking()?;
+
+    let accounts = client.get_accounts()?;
+
+    let from_date = NaiveDate::from_ymd_opt(2023, 10, 1);
+    let to_date = NaiveDate::from_ymd_opt(2023, 10, 3);
+    let t_type = TransactionType::CardPurchases;
+
+    for a in accounts.data.accounts.iter().take(1) {
+        println!("{:#?}", a);
+        let balance = client.get_account_balance(&a.account_id)?;
+        println!("{:#?}\n", balance.data);
+
+        for t in
+            client.account_transactions(&a.account_id, from_date, to_date, Some(t_type.clone()))
+        {
+            println!("{:#?}", t?);
+        }
+    }
+
+    Ok(())
+}
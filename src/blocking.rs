@@ -0,0 +1,271 @@
+//! A blocking client for programs that don't run an async runtime.
+//!
+//! [`Client`] wraps the async [`crate::client::Client`] and drives it on a
+//! private runtime, so it behaves exactly the same (token refresh, retries,
+//! pagination). Like `reqwest::blocking`, it must not be used from within an
+//! async runtime.
+//!
+//! ```no_run
+//! # fn run() -> Result<(), investec::Error> {
+//! let client = investec::blocking::Client::from_env().refresh_auth().build_blocking()?;
+//! let accounts = client.get_accounts()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use chrono::NaiveDate;
+use futures_util::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    client::{self, ClientBuilder, Host, Method},
+    endpoint::Endpoint,
+    request::{MultiTransferRequest, MutliPaymentRequest, Payment, Transfer},
+    response::{
+        Account, AccountBalance, Accounts, Beneficiary, BeneficiaryCategory, MultiPaymentResponse,
+        MultiTransferResponse, Profile, Response, SinglePaymentResponse, SingleTransferResponse,
+        Transaction, TransactionType, Transactions,
+    },
+    token::{AccessToken, AccessTokenResponse},
+    Error,
+};
+
+/// Blocking counterpart of [`crate::client::Client`]. Cheap to clone.
+#[derive(Clone)]
+pub struct Client {
+    inner: client::Client,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl Client {
+    /// wrap an async client
+    pub fn new(inner: client::Client) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// Create a client to the Investec Sandbox environment with a local token store
+    pub fn sandbox() -> Result<Self, Error> {
+        Self::new(client::Client::sandbox())
+    }
+
+    /// read id, secret and key from environment varialbles,
+    /// finish with `ClientBuilder::build_blocking`
+    pub fn from_env() -> ClientBuilder {
+        client::Client::from_env()
+    }
+
+    /// the wrapped async client, sharing token state with this one
+    pub fn as_async(&self) -> &client::Client {
+        &self.inner
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    pub fn host(&self) -> &Host {
+        self.inner.host()
+    }
+
+    pub fn access_token(&self) -> Option<AccessToken> {
+        self.inner.access_token()
+    }
+
+    pub fn set_access_token(&self, token: Option<AccessToken>) {
+        self.inner.set_access_token(token)
+    }
+
+    pub fn get_access_token(&self) -> Result<AccessTokenResponse, Error> {
+        self.block_on(self.inner.get_access_token())
+    }
+
+    pub fn authenticate(&self) -> Result<(), Error> {
+        self.block_on(self.inner.authenticate())
+    }
+
+    pub fn get_accounts(&self) -> Result<Response<Accounts>, Error> {
+        self.block_on(self.inner.get_accounts())
+    }
+
+    pub fn get_account_balance(
+        &self,
+        account_id: impl Into<String>,
+    ) -> Result<Response<AccountBalance>, Error> {
+        self.block_on(self.inner.get_account_balance(account_id))
+    }
+
+    pub fn get_account_transactions(
+        &self,
+        account_id: impl Into<String>,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+    ) -> Result<Response<Transactions>, Error> {
+        self.block_on(self.inner.get_account_transactions(
+            account_id,
+            from_date,
+            to_date,
+            transaction_type,
+        ))
+    }
+
+    pub fn get_account_transactions_page(
+        &self,
+        account_id: impl Into<String>,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+        page: usize,
+    ) -> Result<Response<Transactions>, Error> {
+        self.block_on(self.inner.get_account_transactions_page(
+            account_id,
+            from_date,
+            to_date,
+            transaction_type,
+            page,
+        ))
+    }
+
+    /// iterate over every transaction matching the filters, fetching pages as needed
+    pub fn account_transactions(
+        &self,
+        account_id: impl Into<String>,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+    ) -> TransactionIter {
+        let stream =
+            self.inner
+                .account_transactions(account_id, from_date, to_date, transaction_type);
+        TransactionIter {
+            stream: Box::pin(stream),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    pub fn get_profiles(&self) -> Result<Response<Vec<Profile>>, Error> {
+        self.block_on(self.inner.get_profiles())
+    }
+
+    pub fn get_profile_accounts(
+        &self,
+        profile_id: impl Into<String>,
+    ) -> Result<Response<Vec<Account>>, Error> {
+        self.block_on(self.inner.get_profile_accounts(profile_id))
+    }
+
+    pub fn get_auth_setup_details(
+        &self,
+        profile_id: impl Into<String>,
+        account_id: impl Into<String>,
+    ) -> Result<Response<serde_json::Value>, Error> {
+        self.block_on(self.inner.get_auth_setup_details(profile_id, account_id))
+    }
+
+    pub fn get_profile_beneficiaries(
+        &self,
+        profile_id: impl Into<String>,
+        account_id: impl Into<String>,
+    ) -> Result<Response<Vec<Beneficiary>>, Error> {
+        self.block_on(self.inner.get_profile_beneficiaries(profile_id, account_id))
+    }
+
+    pub fn get_beneficiaries(&self) -> Result<Response<Vec<Beneficiary>>, Error> {
+        self.block_on(self.inner.get_beneficiaries())
+    }
+
+    pub fn transfer_multiple(
+        &self,
+        account_id: impl Into<String>,
+        transfer_list: MultiTransferRequest,
+    ) -> Result<Response<MultiTransferResponse>, Error> {
+        self.block_on(self.inner.transfer_multiple(account_id, transfer_list))
+    }
+
+    pub fn transfer_single(
+        &self,
+        account_id: impl Into<String>,
+        request: Transfer,
+        profile_id: impl Into<Option<String>>,
+    ) -> Result<SingleTransferResponse, Error> {
+        self.block_on(self.inner.transfer_single(account_id, request, profile_id))
+    }
+
+    pub fn get_beneficiary_categories(&self) -> Result<Response<Vec<BeneficiaryCategory>>, Error> {
+        self.block_on(self.inner.get_beneficiary_categories())
+    }
+
+    pub fn pay_multiple(
+        &self,
+        account_id: impl Into<String>,
+        payment_list: MutliPaymentRequest,
+    ) -> Result<Response<MultiPaymentResponse>, Error> {
+        self.block_on(self.inner.pay_multiple(account_id, payment_list))
+    }
+
+    pub fn pay_single(
+        &self,
+        account_id: impl Into<String>,
+        payment: Payment,
+    ) -> Result<SinglePaymentResponse, Error> {
+        self.block_on(self.inner.pay_single(account_id, payment))
+    }
+
+    /// see [`crate::client::Client::execute`]
+    pub fn execute<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        self.block_on(self.inner.execute(endpoint))
+    }
+
+    /// see [`crate::client::Client::request`]
+    pub fn request<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&B>,
+    ) -> Result<Response<T>, Error> {
+        self.block_on(self.inner.request(method, path, query, body))
+    }
+
+    /// see [`crate::client::Client::invalidate`]
+    pub fn invalidate<E: Endpoint>(&self, endpoint: &E) -> Result<(), Error> {
+        self.inner.invalidate(endpoint)
+    }
+
+    pub fn clear_cache(&self) -> Result<(), Error> {
+        self.inner.clear_cache()
+    }
+}
+
+/// Iterator returned by [`Client::account_transactions`]
+pub struct TransactionIter {
+    stream: Pin<Box<dyn Stream<Item = Result<Transaction, Error>> + Send>>,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl Iterator for TransactionIter {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
+
+impl ClientBuilder {
+    /// build a [`Client`] instead of the async one
+    pub fn build_blocking(self) -> Result<Client, Error> {
+        Client::new(self.build()?)
+    }
+}
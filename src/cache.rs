@@ -0,0 +1,208 @@
+//! Caching of responses that hardly ever change.
+//!
+//! A [`Cache`] set with [`crate::client::ClientBuilder::cache`] serves GET
+//! endpoints from a [`CacheBackend`] for as long as their TTL. Only profiles,
+//! accounts, beneficiaries and beneficiary categories have one by default, so
+//! balances and transactions are always fetched unless given a TTL too.
+//!
+//! ```
+//! use std::time::Duration;
+//! use investec::cache::Cache;
+//!
+//! let cache = Cache::memory()
+//!     .ttl("get_accounts", Duration::from_secs(60 * 60))
+//!     // opt in to balances up to a minute old
+//!     .ttl("get_account_balance", Duration::from_secs(60));
+//! ```
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+/// A cached response body
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub body: Vec<u8>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CacheEntry {
+    pub fn new(body: Vec<u8>, ttl: Duration) -> Self {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        let expires_at = Utc::now()
+            .checked_add_signed(ttl)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        Self { body, expires_at }
+    }
+
+    pub fn expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+}
+
+/// Where cached responses are kept. Keys identify the client and request.
+pub trait CacheBackend: Send + Sync {
+    fn get(&self, key: &str) -> anyhow::Result<Option<CacheEntry>>;
+    fn set(&self, key: &str, entry: CacheEntry) -> anyhow::Result<()>;
+    fn remove(&self, key: &str) -> anyhow::Result<()>;
+    fn clear(&self) -> anyhow::Result<()>;
+}
+
+/// Which endpoints are cached for how long, and where
+#[derive(Clone)]
+pub struct Cache {
+    pub(crate) backend: Arc<dyn CacheBackend>,
+    ttls: HashMap<String, Duration>,
+}
+
+impl Cache {
+    /// cache in `backend`, with the default TTLs
+    pub fn new(backend: impl CacheBackend + 'static) -> Self {
+        let ttl = Duration::from_secs(5 * 60);
+        let ttls = [
+            "get_profiles",
+            "get_profile_accounts",
+            "get_accounts",
+            "get_beneficiaries",
+            "get_beneficiary_categories",
+        ]
+        .into_iter()
+        .map(|endpoint| (endpoint.to_string(), ttl))
+        .collect();
+        Self {
+            backend: Arc::new(backend),
+            ttls,
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryCache::default())
+    }
+
+    /// cache an endpoint, by its [`crate::endpoint::Endpoint::name`].
+    /// A zero TTL turns caching off for it.
+    pub fn ttl(mut self, endpoint: impl Into<String>, ttl: Duration) -> Self {
+        let endpoint = endpoint.into();
+        if ttl.is_zero() {
+            self.ttls.remove(&endpoint);
+        } else {
+            self.ttls.insert(endpoint, ttl);
+        }
+        self
+    }
+
+    pub(crate) fn ttl_of(&self, endpoint: &str) -> Option<Duration> {
+        self.ttls.get(endpoint).copied()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl CacheBackend for MemoryCache {
+    fn get(&self, key: &str) -> anyhow::Result<Option<CacheEntry>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| !entry.expired());
+        entries.insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// Keeps each entry in a file in `dir`, so the cache survives restarts.
+/// The files hold account data, so like token files they are only readable
+/// by their owner and replaced atomically.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct DiskCache {
+    pub dir: PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DiskCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // FNV-1a, stable across builds unlike `DefaultHasher`
+        let hash = key.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        self.dir.join(format!("{hash:016x}.cache"))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CacheBackend for DiskCache {
+    fn get(&self, key: &str) -> anyhow::Result<Option<CacheEntry>> {
+        let file = match std::fs::read(self.path(key)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            file => file?,
+        };
+        // the key, the expiry and the body, separated by newlines
+        let mut parts = file.splitn(3, |b| *b == b'\n');
+        let (Some(stored_key), Some(expires_at), Some(body)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Ok(None);
+        };
+        // another key with the same hash
+        if stored_key != key.as_bytes() {
+            return Ok(None);
+        }
+        let expires_at = DateTime::parse_from_rfc3339(std::str::from_utf8(expires_at)?)?;
+        Ok(Some(CacheEntry {
+            body: body.to_vec(),
+            expires_at: expires_at.with_timezone(&Utc),
+        }))
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> anyhow::Result<()> {
+        let mut file = format!("{key}\n{}\n", entry.expires_at.to_rfc3339()).into_bytes();
+        file.extend_from_slice(&entry.body);
+        crate::token::write_private(&self.path(key), &file)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            entries => entries?,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "cache") {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
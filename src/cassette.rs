@@ -0,0 +1,447 @@
+//! Record real API interactions and replay them in tests.
+//!
+//! A [`Recorder`] is a [`Middleware`] that keeps every request the client sends
+//! and the response it got. Saved as a [`Cassette`], with access tokens and
+//! account, profile and beneficiary numbers replaced by placeholders, it can be
+//! replayed by a [`ReplayServer`] without network access:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), investec::Error> {
+//! use investec::{cassette::{Cassette, Recorder}, client::Client};
+//!
+//! // once, against the sandbox
+//! let recorder = Recorder::new();
+//! let client = Client::from_env().sandbox().refresh_auth().middleware(recorder.clone()).build()?;
+//! client.get_accounts().await?;
+//! recorder.save("tests/cassettes/accounts.json")?;
+//!
+//! // in tests
+//! let server = Cassette::load("tests/cassettes/accounts.json")?.serve();
+//! let accounts = server.client().get_accounts().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests are matched on method, path, query and body. A request the
+//! cassette has no interaction for is answered with `501 Not Implemented`, and
+//! the server panics when dropped, so tests can't silently pass on stale
+//! cassettes. Request headers are not recorded, they carry the credentials.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use hyper::{Body, Request, StatusCode};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{Client, ClientBuilder, Host},
+    middleware::Middleware,
+    mock::{bind, serve, MockResponse},
+    Error,
+};
+
+/// json fields holding account, profile and beneficiary numbers
+const SCRUBBED_FIELDS: [&str; 6] = [
+    "accountId",
+    "accountNumber",
+    "beneficiaryAccountId",
+    "beneficiaryId",
+    "cardNumber",
+    "profileId",
+];
+
+/// json fields holding tokens
+const TOKEN_FIELDS: [&str; 2] = ["access_token", "refresh_token"];
+
+const SCRUBBED_TOKEN: &str = "scrubbed-token";
+
+/// Recorded interactions, in the order they happened
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Option<RecordedBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<RecordedBody>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedBody {
+    Json(Value),
+    Text(String),
+}
+
+impl RecordedBody {
+    fn new(body: &[u8]) -> Option<Self> {
+        if body.is_empty() {
+            return None;
+        }
+        match serde_json::from_slice(body) {
+            Ok(json) => Some(Self::Json(json)),
+            Err(_) => Some(Self::Text(String::from_utf8_lossy(body).into_owned())),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Json(json) => json.to_string().into_bytes(),
+            Self::Text(text) => text.clone().into_bytes(),
+        }
+    }
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&file)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// replay the interactions from a local server
+    pub fn serve(self) -> ReplayServer {
+        ReplayServer::start(self)
+    }
+
+    /// Replace tokens with a placeholder, and account, profile and beneficiary
+    /// numbers and `extra` values with zero padded counters wherever they occur.
+    /// The same value always gets the same placeholder, so replayed requests
+    /// can use the ids of replayed responses. Ids shorter than 4 characters
+    /// are left alone, `extra` values of any length are scrubbed.
+    pub fn scrub(&mut self, extra: &[String]) {
+        let mut values = Vec::new();
+        for interaction in &mut self.interactions {
+            for body in [
+                &mut interaction.request.body,
+                &mut interaction.response.body,
+            ] {
+                if let Some(RecordedBody::Json(json)) = body {
+                    collect(json, &mut values);
+                }
+            }
+        }
+        // short ids would match all over the place, but values asked for are
+        // scrubbed however short
+        let extra = extra.iter().filter(|value| !value.is_empty()).cloned();
+        let values = values.into_iter().filter(|value| value.len() >= 4);
+        let mut placeholders: HashMap<String, String> = HashMap::new();
+        for value in extra.chain(values) {
+            if !placeholders.contains_key(&value) {
+                let placeholder =
+                    format!("{:0>width$}", placeholders.len() + 1, width = value.len());
+                placeholders.insert(value, placeholder);
+            }
+        }
+        let mut placeholders: Vec<_> = placeholders.into_iter().collect();
+        // longest first, an id may contain a shorter one
+        placeholders.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(&b.0)));
+        let replace = |text: &mut String| {
+            for (value, placeholder) in &placeholders {
+                if text.contains(value.as_str()) {
+                    *text = text.replace(value.as_str(), placeholder);
+                }
+            }
+        };
+
+        for interaction in &mut self.interactions {
+            let request = &mut interaction.request;
+            replace(&mut request.path);
+            if let Some(query) = &mut request.query {
+                replace(query);
+            }
+            let response = &mut interaction.response;
+            for value in response.headers.values_mut() {
+                replace(value);
+            }
+            for body in [&mut request.body, &mut response.body] {
+                match body {
+                    Some(RecordedBody::Json(json)) => replace_json(json, &replace),
+                    Some(RecordedBody::Text(text)) => replace(text),
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+/// scrubbed values in `json`, with tokens replaced right away
+fn collect(json: &mut Value, values: &mut Vec<String>) {
+    match json {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                if TOKEN_FIELDS.contains(&name.as_str()) {
+                    *value = json!(SCRUBBED_TOKEN);
+                } else if let (true, Value::String(s)) =
+                    (SCRUBBED_FIELDS.contains(&name.as_str()), &value)
+                {
+                    values.push(s.clone());
+                } else {
+                    collect(value, values);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| collect(item, values)),
+        _ => {}
+    }
+}
+
+fn replace_json(json: &mut Value, replace: &impl Fn(&mut String)) {
+    match json {
+        Value::String(s) => replace(s),
+        Value::Object(fields) => fields.values_mut().for_each(|v| replace_json(v, replace)),
+        Value::Array(items) => items.iter_mut().for_each(|v| replace_json(v, replace)),
+        _ => {}
+    }
+}
+
+/// [`Middleware`] recording every interaction of a client.
+/// Clones share the recording, keep one to save it.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+    scrubbed: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// also scrub `value` wherever it occurs, e.g. a name or reference
+    pub fn scrub(&self, value: impl Into<String>) {
+        self.scrubbed.lock().unwrap().push(value.into());
+    }
+
+    /// the interactions so far, scrubbed
+    pub fn cassette(&self) -> Cassette {
+        let mut cassette = Cassette {
+            interactions: self.interactions.lock().unwrap().clone(),
+        };
+        cassette.scrub(&self.scrubbed.lock().unwrap());
+        cassette
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.cassette().save(path)
+    }
+}
+
+impl Middleware for Recorder {
+    fn on_response(
+        &self,
+        request: &reqwest::Request,
+        response: &reqwest::Response,
+        body: Option<&[u8]>,
+    ) {
+        let url = request.url();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| *name != "set-cookie")
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: request.method().to_string(),
+                path: url.path().to_string(),
+                query: url.query().map(str::to_string),
+                body: request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .and_then(RecordedBody::new),
+            },
+            response: RecordedResponse {
+                status: response.status().as_u16(),
+                headers,
+                body: body.and_then(RecordedBody::new),
+            },
+        };
+        self.interactions.lock().unwrap().push(interaction);
+    }
+}
+
+struct State {
+    interactions: Vec<Interaction>,
+    // times each interaction was replayed
+    replayed: Mutex<Vec<usize>>,
+    unmatched: Mutex<Vec<String>>,
+}
+
+/// A local server answering requests from a [`Cassette`]. Shuts down when
+/// dropped, panicking if any request had no recorded interaction.
+pub struct ReplayServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ReplayServer {
+    pub fn start(cassette: Cassette) -> Self {
+        let (listener, addr) = bind();
+        let state = Arc::new(State {
+            replayed: Mutex::new(vec![0; cassette.interactions.len()]),
+            interactions: cassette.interactions,
+            unmatched: Mutex::new(Vec::new()),
+        });
+        let (shutdown, thread) = serve(listener, state.clone(), replay);
+        Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}", self.addr)).expect("replay server url")
+    }
+
+    pub fn host(&self) -> Host {
+        Host::Custom(self.url())
+    }
+
+    /// A builder with placeholder credentials and the host set.
+    /// Credentials aren't checked, the recorded token response is replayed.
+    pub fn builder(&self) -> ClientBuilder {
+        ClientBuilder::new()
+            .id("replay".to_string())
+            .secret("replay".to_string())
+            .key("replay".to_string())
+            .host(self.host())
+    }
+
+    /// A client for this server with auto refresh and no token store, so it
+    /// requests a token like the recorded client did
+    pub fn client(&self) -> Client {
+        self.builder()
+            .refresh_auth()
+            .build()
+            .expect("replay client")
+    }
+
+    /// Requests without a recorded interaction, as `"METHOD /path?query"`.
+    /// Taking them stops the server from panicking on drop.
+    pub fn take_unmatched(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.unmatched.lock().unwrap())
+    }
+}
+
+impl Drop for ReplayServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let unmatched = self.take_unmatched();
+        if !unmatched.is_empty() && !std::thread::panicking() {
+            panic!("requests not in the cassette: {unmatched:?}");
+        }
+    }
+}
+
+/// the first interaction matching the request that hasn't been replayed yet,
+/// or else the last one matching it
+async fn replay(state: Arc<State>, req: Request<Body>) -> Result<MockResponse, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let body = RecordedBody::new(&body);
+    let method = parts.method.to_string();
+    let path = parts.uri.path();
+    let query = parts.uri.query();
+
+    let matching: Vec<usize> = state
+        .interactions
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| {
+            i.request.method == method
+                && i.request.path == path
+                && i.request.query.as_deref() == query
+                && i.request.body == body
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let index = {
+        let mut replayed = state.replayed.lock().unwrap();
+        let index = matching
+            .iter()
+            .find(|index| replayed[**index] == 0)
+            .or(matching.last())
+            .copied();
+        if let Some(index) = index {
+            replayed[index] += 1;
+        }
+        index
+    };
+
+    let Some(index) = index else {
+        let request = match query {
+            Some(query) => format!("{method} {path}?{query}"),
+            None => format!("{method} {path}"),
+        };
+        let message = format!("no interaction recorded for {request}");
+        state.unmatched.lock().unwrap().push(request);
+        let mut resp = MockResponse::new(Body::from(json!({ "message": message }).to_string()));
+        *resp.status_mut() = StatusCode::NOT_IMPLEMENTED;
+        return Ok(resp);
+    };
+
+    let recorded = &state.interactions[index].response;
+    let body = recorded
+        .body
+        .as_ref()
+        .map(RecordedBody::to_bytes)
+        .unwrap_or_default();
+    let mut resp = MockResponse::new(Body::from(body));
+    *resp.status_mut() = StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK);
+    for (name, value) in &recorded.headers {
+        // the body is re-encoded, its length may differ
+        if name == "content-length" || name == "transfer-encoding" {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(name.as_bytes()),
+            hyper::header::HeaderValue::from_str(value),
+        ) {
+            resp.headers_mut().insert(name, value);
+        }
+    }
+    Ok(resp)
+}
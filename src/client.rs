@@ -1,27 +1,77 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use chrono::NaiveDate;
-use reqwest::Method;
+use futures_util::{stream, Stream};
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION},
+    StatusCode,
+};
+pub use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
+    cache::{Cache, CacheEntry},
+    endpoint::{
+        Endpoint, GetAccountBalance, GetAccountTransactions, GetAccounts, GetAuthSetupDetails,
+        GetBeneficiaries, GetBeneficiaryCategories, GetProfileAccounts, GetProfileBeneficiaries,
+        GetProfiles, PayMultiple, TransferMultiple,
+    },
+    meta,
+    middleware::Middleware,
+    rate_limit::{EndpointGroup, RateLimit, RateLimiter},
     request::{MultiTransferRequest, MutliPaymentRequest, Payment, Transfer},
     response::{
         Account, AccountBalance, Accounts, Beneficiary, BeneficiaryCategory, MultiPaymentResponse,
         MultiTransferResponse, Profile, Response, SinglePaymentResponse, SingleTransferResponse,
-        TransactionType, Transactions,
+        Transaction, TransactionType, Transactions,
+    },
+    retry::RetryPolicy,
+    token::{
+        self, AccessToken, AccessTokenResponse, AsyncTokenStore, IntoTokenStore, Scope, TokenKey,
     },
-    token::{AccessToken, AccessTokenResponse, FileStore, TokenStore},
-    Error,
+    trace, Error,
 };
 
+pub(crate) const SANDBOX_CLIENT_ID: &str = "yAxzQRFX97vOcyQAwluEU6H6ePxMA5eY";
+pub(crate) const SANDBOX_CLIENT_SECRET: &str = "4dY0PjEYqoBrZ99r";
+pub(crate) const SANDBOX_API_KEY: &str = "eUF4elFSRlg5N3ZPY3lRQXdsdUVVNkg2ZVB4TUE1ZVk6YVc1MlpYTjBaV010ZW1FdGNHSXRZV05qYjNWdWRITXRjMkZ1WkdKdmVBPT0=";
+
+/// how long before `expires_at` a token is treated as expired by default, so
+/// requests don't race its expiry or a skewed clock
+pub const DEFAULT_TOKEN_LEEWAY: Duration = Duration::from_secs(30);
+
+/// Client for the Investec APIs.
+///
+/// Cloning is cheap: all clones share the same http client, token store and
+/// access token, so one client can be used from many tasks at once.
+#[derive(Clone)]
 pub struct Client {
-    pub id: String,
-    pub secret: String,
-    pub key: String,
-    pub host: Host,
-    pub access_token: Option<AccessToken>,
-    pub token_store: Option<Box<dyn TokenStore>>,
-    pub refresh_auth: bool,
+    inner: Arc<ClientRef>,
+}
+
+struct ClientRef {
+    id: String,
+    secret: String,
+    key: String,
+    host: Host,
+    access_token: RwLock<Option<AccessToken>>,
+    // held while fetching a new token so concurrent callers wait for it
+    // instead of each requesting their own
+    refresh_lock: tokio::sync::Mutex<()>,
+    token_store: Option<Box<dyn AsyncTokenStore>>,
+    refresh_auth: bool,
+    token_leeway: Duration,
+    // scopes to request tokens for, all the client is allowed if empty
+    scopes: Vec<Scope>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limiter: RateLimiter,
+    middleware: Vec<Arc<dyn Middleware>>,
+    cache: Option<Cache>,
     http_client: reqwest::Client,
 }
 
@@ -29,274 +79,680 @@ impl Client {
     /// Create a client to the Investec Sandbox environment with a local token store
     pub fn sandbox() -> Self {
         Self {
-            id: "yAxzQRFX97vOcyQAwluEU6H6ePxMA5eY".to_string(),
-            secret: "4dY0PjEYqoBrZ99r".to_string(),
-            key: "eUF4elFSRlg5N3ZPY3lRQXdsdUVVNkg2ZVB4TUE1ZVk6YVc1MlpYTjBaV010ZW1FdGNHSXRZV05qYjNWdWRITXRjMkZ1WkdKdmVBPT0=".to_string(),
-            host: Host::Sandbox,
-            access_token: None,
-            token_store: Some(Box::new(FileStore::default())),
-            refresh_auth: true,
-            http_client: reqwest::Client::new(),
+            inner: Arc::new(ClientRef {
+                id: SANDBOX_CLIENT_ID.to_string(),
+                secret: SANDBOX_CLIENT_SECRET.to_string(),
+                key: SANDBOX_API_KEY.to_string(),
+                host: Host::Sandbox,
+                access_token: RwLock::new(None),
+                refresh_lock: tokio::sync::Mutex::new(()),
+                token_store: Some(token::local_store(&TokenKey::new(
+                    &Host::Sandbox,
+                    SANDBOX_CLIENT_ID,
+                ))),
+                refresh_auth: true,
+                token_leeway: DEFAULT_TOKEN_LEEWAY,
+                scopes: Vec::new(),
+                retry_policy: None,
+                rate_limiter: RateLimiter::default(),
+                middleware: Vec::new(),
+                cache: None,
+                http_client: reqwest::Client::new(),
+            }),
         }
     }
-    /// read id, secret and key from environment varialbles
+    /// read id, secret and key from the `INVESTEC_CLIENT_ID`, `INVESTEC_CLIENT_SECRET`
+    /// and `INVESTEC_API_KEY` environment variables. Missing ones are reported
+    /// by `build` as `Error::ClientFieldUndefined`.
     pub fn from_env() -> ClientBuilder {
-        let id = Some(std::env::var("INVESTEC_CLIENT_ID").expect("INVESTEC_CLIENT_ID"));
-        let secret = Some(std::env::var("INVESTEC_CLIENT_SECRET").expect("INVESTEC_CLIENT_SECRET"));
-        let key = Some(std::env::var("INVESTEC_API_KEY").expect("INVESTEC_API_KEY"));
-
         ClientBuilder {
-            id,
-            secret,
-            key,
+            id: std::env::var("INVESTEC_CLIENT_ID").ok(),
+            secret: std::env::var("INVESTEC_CLIENT_SECRET").ok(),
+            key: std::env::var("INVESTEC_API_KEY").ok(),
             host: Some(Host::Live),
-            token_store: None,
-            refresh_auth: None,
+            ..ClientBuilder::new()
         }
     }
 
-    /// Get access token
+    pub fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    pub fn host(&self) -> &Host {
+        &self.inner.host
+    }
+
+    /// The access token currently held by the client, if any
+    pub fn access_token(&self) -> Option<AccessToken> {
+        self.inner.access_token.read().unwrap().clone()
+    }
+
+    /// Replace the access token held by the client (and all its clones)
+    pub fn set_access_token(&self, token: Option<AccessToken>) {
+        *self.inner.access_token.write().unwrap() = token;
+    }
+
+    /// Get access token, for the [`ClientBuilder::scopes`] if set
     pub async fn get_access_token(&self) -> Result<AccessTokenResponse, Error> {
-        let url = format!("{}/identity/v2/oauth2/token", self.host.url());
+        let call = self.request_access_token();
+        trace::instrument(
+            call,
+            "get_access_token",
+            &Method::POST,
+            "/identity/v2/oauth2/token",
+        )
+        .await
+    }
+
+    async fn request_access_token(&self) -> Result<AccessTokenResponse, Error> {
+        let url = format!("{}/identity/v2/oauth2/token", self.inner.host.url());
         let mut params = HashMap::new();
-        params.insert("grant_type", "client_credentials");
-        let resp = self
+        params.insert("grant_type", "client_credentials".to_string());
+        if !self.inner.scopes.is_empty() {
+            let scopes: Vec<_> = self.inner.scopes.iter().map(Scope::as_str).collect();
+            params.insert("scope", scopes.join(" "));
+        }
+        let request = self
+            .inner
             .http_client
             .post(url)
-            .basic_auth(&self.id, Some(&self.secret))
-            .header("x-api-key", &self.key)
+            .basic_auth(&self.inner.id, Some(&self.inner.secret))
+            .header("x-api-key", &self.inner.key)
             .form(&params)
-            .send()
+            .build()?;
+        let _latency = trace::Latency::start();
+        // a client credentials grant has no side effects, so it is safe to retry
+        let resp = self
+            .send_with_retry(request, true, EndpointGroup::Auth)
             .await?;
-        let resp = resp.error_for_status()?;
+        let resp = error_for_status_with_text(resp).await?;
         let token: AccessTokenResponse = resp.json().await?;
         Ok(token)
     }
 
+    /// host, client id and scopes that tokens of this client are issued for
+    pub fn token_key(&self) -> TokenKey {
+        TokenKey::new(&self.inner.host, &self.inner.id).with_scopes(self.inner.scopes.clone())
+    }
+
+    /// whether `token` is still good for a request, see [`ClientBuilder::token_leeway`]
+    fn usable(&self, token: &AccessToken) -> bool {
+        !token.expires_within(self.inner.token_leeway)
+    }
+
     /// exchange client credentials for access token if tokens in caches don't exist
-    /// or expired. Cache if new token is fetched.
-    pub async fn authenticate(&mut self) -> Result<(), Error> {
-        if let Some(token) = &self.access_token {
-            if !token.expired() {
+    /// or expire within the leeway. Cache if new token is fetched.
+    /// Only one refresh is in flight at a time, concurrent callers share its result.
+    pub async fn authenticate(&self) -> Result<(), Error> {
+        if self.access_token().is_some_and(|token| self.usable(&token)) {
+            return Ok(());
+        }
+
+        let _refresh = self.inner.refresh_lock.lock().await;
+        // another caller may have refreshed while we were waiting, or another
+        // client sharing the token store
+        if self.access_token().is_some_and(|token| self.usable(&token)) {
+            return Ok(());
+        }
+        if let Some(token) = self.stored_token(self.inner.token_leeway).await {
+            self.set_access_token(Some(token));
+            return Ok(());
+        }
+
+        self.fetch_token().await
+    }
+
+    /// the token in the token store, if it was issued for this client and
+    /// doesn't expire within `leeway`
+    async fn stored_token(&self, leeway: Duration) -> Option<AccessToken> {
+        let token_store = self.inner.token_store.as_ref()?;
+        let token = token_store.read().await.ok()??;
+        // a token for another host or client would be rejected, or worse, accepted.
+        // One requested for other scopes may lack some this client needs
+        let key = self.token_key();
+        (!token.expires_within(leeway) && token.issued_for.as_ref() == Some(&key)).then_some(token)
+    }
+
+    /// refresh the token if it expires within `ahead`, taking a newer one from
+    /// the token store if another client already refreshed it
+    async fn refresh_expiring_token(&self, ahead: Duration) -> Result<(), Error> {
+        let _refresh = self.inner.refresh_lock.lock().await;
+        if self
+            .access_token()
+            .is_some_and(|token| !token.expires_within(ahead))
+        {
+            return Ok(());
+        }
+        if let Some(token) = self.stored_token(ahead).await {
+            self.set_access_token(Some(token));
+            return Ok(());
+        }
+        self.fetch_token().await
+    }
+
+    /// Refresh the access token in the background, `ahead` of its expiry, so
+    /// requests never wait for a token. Make `ahead` longer than the
+    /// [`ClientBuilder::token_leeway`], or requests may still refresh first.
+    /// `ahead` is capped at half the lifetime of the tokens, so a long one
+    /// doesn't keep fetching tokens.
+    ///
+    /// Failed refreshes are retried every few seconds, and reported as
+    /// `tracing` events with that feature. The task ends once all
+    /// clones of the client are dropped, or when the handle is aborted.
+    /// Must be called from a tokio runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn_token_refresh(&self, ahead: Duration) -> tokio::task::JoinHandle<()> {
+        const RETRY_AFTER: Duration = Duration::from_secs(5);
+        // never spin on tokens that expire right away
+        const MIN_WAIT: Duration = Duration::from_secs(1);
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            // longest time left on a token seen right after a refresh, which
+            // is about the lifetime of new tokens
+            let mut lifetime = Duration::ZERO;
+            let capped = |lifetime: Duration| {
+                if lifetime.is_zero() {
+                    ahead
+                } else {
+                    ahead.min(lifetime / 2)
+                }
+            };
+            let left = |client: &Client| {
+                client
+                    .access_token()
+                    .and_then(|token| (token.expires_at - chrono::Utc::now()).to_std().ok())
+            };
+            while let Some(inner) = inner.upgrade() {
+                let client = Client { inner };
+                // a token the client already holds caps the first pass too
+                lifetime = lifetime.max(left(&client).unwrap_or_default());
+                let wait = match client.refresh_expiring_token(capped(lifetime)).await {
+                    Ok(()) => left(&client).map_or(MIN_WAIT, |left| {
+                        lifetime = lifetime.max(left);
+                        left.saturating_sub(capped(lifetime)).max(MIN_WAIT)
+                    }),
+                    Err(error) => {
+                        trace::token_refresh_failed(&error);
+                        RETRY_AFTER
+                    }
+                };
+                drop(client);
+                tokio::time::sleep(wait).await;
+            }
+        })
+    }
+
+    /// discard a token the API rejected, from memory and the token store, and
+    /// fetch a new one. If another caller or worker already replaced it, that
+    /// token is used.
+    async fn refresh_rejected_token(&self, rejected: &str) -> Result<(), Error> {
+        let _refresh = self.inner.refresh_lock.lock().await;
+        if self
+            .access_token()
+            .is_some_and(|token| token.access_token != rejected && self.usable(&token))
+        {
+            return Ok(());
+        }
+
+        if let Some(token) = self.stored_token(self.inner.token_leeway).await {
+            if token.access_token != rejected {
+                self.set_access_token(Some(token));
                 return Ok(());
             }
-        } else if let Some(token_store) = &self.token_store {
-            if let Ok(token) = token_store.read() {
-                if !token.expired() {
-                    self.access_token = Some(token);
-                    return Ok(());
-                }
+        }
+
+        self.set_access_token(None);
+        if let Some(token_store) = &self.inner.token_store {
+            // another worker may have stored a replacement that isn't usable here
+            let stored = token_store.read().await.ok().flatten();
+            if stored.is_some_and(|token| token.access_token == rejected) {
+                token_store.clear().await?;
             }
         }
+        self.fetch_token().await
+    }
 
-        let token = self.get_access_token().await?.into();
-        if let Some(token_store) = &self.token_store {
-            token_store.write(&token)?;
+    // callers must hold `refresh_lock`
+    async fn fetch_token(&self) -> Result<(), Error> {
+        let token = AccessToken {
+            issued_for: Some(self.token_key()),
+            ..self.get_access_token().await?.into()
+        };
+        if let Some(token_store) = &self.inner.token_store {
+            token_store.write(&token).await?;
         }
-        self.access_token = Some(token);
+        self.set_access_token(Some(token));
+        trace::record_token_refresh();
 
         Ok(())
     }
 
     /// helper function to reduce repetitve code for autorefresh and http client setup
-    // async fn default_get(&mut self, url: String) -> Result<reqwest::RequestBuilder, Error> {
     async fn default_request(
-        &mut self,
+        &self,
         method: Method,
         url: String,
     ) -> Result<reqwest::RequestBuilder, Error> {
-        if self.refresh_auth {
+        if self.inner.refresh_auth {
             self.authenticate().await?;
         }
-        match &self.access_token {
+        match self.access_token() {
             Some(token) => {
                 let resp = self
+                    .inner
                     .http_client
                     .request(method, url)
                     .bearer_auth(&token.access_token);
-                // let resp = self.http_client.get(url).bearer_auth(&token.access_token);
                 Ok(resp)
             }
             None => Err(Error::NoAccessToken),
         }
     }
 
-    pub async fn get_accounts(&mut self) -> Result<Response<Accounts>, Error> {
-        let url = format!("{}/za/pb/v1/accounts", self.host.url());
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-        let data = resp.json().await?;
+    /// send a request, retrying transient failures if it is idempotent.
+    /// With `refresh_auth`, a 401 gets the token refreshed and the request replayed once.
+    /// `group` is the rate limit the request counts against.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+        group: EndpointGroup,
+    ) -> Result<reqwest::Response, Error> {
+        let _latency = trace::Latency::start();
+        let request = request.build()?;
+        let idempotent = request.method().is_idempotent();
+        let replay = if self.inner.refresh_auth {
+            request.try_clone()
+        } else {
+            None
+        };
+
+        let resp = self.send_with_retry(request, idempotent, group).await?;
+        let Some(mut replay) = replay else {
+            return Ok(resp);
+        };
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let rejected = replay
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .to_string();
+        self.refresh_rejected_token(&rejected).await?;
+        let token = self.access_token().ok_or(Error::NoAccessToken)?;
+        let bearer = HeaderValue::from_str(&format!("Bearer {}", token.access_token))
+            .map_err(|e| Error::CustomRequest(e.to_string()))?;
+        replay.headers_mut().insert(AUTHORIZATION, bearer);
+
+        self.send_with_retry(replay, idempotent, group).await
+    }
+
+    async fn send_with_retry(
+        &self,
+        request: reqwest::Request,
+        retry: bool,
+        group: EndpointGroup,
+    ) -> Result<reqwest::Response, Error> {
+        let policy = match &self.inner.retry_policy {
+            Some(policy) if retry => policy,
+            _ => return self.send_once(request, group).await,
+        };
+
+        let mut attempt = 0;
+        loop {
+            // bodies are always buffered here, so cloning only fails for streams
+            let Some(req) = request.try_clone() else {
+                return self.send_once(request, group).await;
+            };
+            let wait = match self.send_once(req, group).await {
+                Ok(resp) if RetryPolicy::is_retryable_status(resp.status()) => {
+                    if attempt >= policy.max_retries {
+                        return Ok(resp);
+                    }
+                    match policy.wait_for(&resp, attempt) {
+                        Some(wait) => wait,
+                        None => return Ok(resp),
+                    }
+                }
+                Ok(resp) => return Ok(resp),
+                Err(Error::Request(err)) if RetryPolicy::is_retryable_error(&err) => {
+                    if attempt >= policy.max_retries {
+                        return Err(err.into());
+                    }
+                    policy.backoff(attempt)
+                }
+                Err(err) => return Err(err),
+            };
+            crate::retry::sleep(wait).await;
+            attempt += 1;
+            trace::record_retries(attempt);
+        }
+    }
+
+    /// send a single attempt through the rate limiter and middleware
+    async fn send_once(
+        &self,
+        mut request: reqwest::Request,
+        group: EndpointGroup,
+    ) -> Result<reqwest::Response, Error> {
+        self.inner.rate_limiter.acquire(group).await;
+        let middleware = &self.inner.middleware;
+        for m in middleware {
+            m.on_request(&mut request)?;
+        }
+        let observed = if middleware.is_empty() {
+            None
+        } else {
+            request.try_clone()
+        };
+
+        // `Instant` isn't available in the browser
+        let started = chrono::Utc::now();
+        let resp = match self.inner.http_client.execute(request).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                if let Some(observed) = &observed {
+                    for m in middleware {
+                        m.on_error(observed, &err);
+                    }
+                }
+                return Err(err.into());
+            }
+        };
+        trace::record_status(resp.status());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let (resp, body) = if observed.is_some() || meta::is_capturing() {
+            let (resp, body) = buffer_response(resp).await?;
+            (resp, Some(body))
+        } else {
+            (resp, None)
+        };
+        #[cfg(target_arch = "wasm32")]
+        let body: Option<&[u8]> = None;
+
+        let elapsed = (chrono::Utc::now() - started).to_std().unwrap_or_default();
+        meta::record(&resp, elapsed, body.as_deref());
+        if let Some(observed) = &observed {
+            for m in middleware {
+                m.on_response(observed, &resp, body.as_deref());
+            }
+        }
+        Ok(resp)
+    }
+
+    /// Call an [`Endpoint`], refreshing the token, retrying and tracing like
+    /// every built-in method. Error responses become [`Error::Api`].
+    pub async fn execute<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        let call = self.call(endpoint);
+        trace::instrument(
+            call,
+            endpoint.name(),
+            &endpoint.method(),
+            endpoint.path_template(),
+        )
+        .await
+    }
+
+    async fn call<E: Endpoint>(&self, endpoint: &E) -> Result<E::Response, Error> {
+        let url = format!("{}{}", self.inner.host.url(), endpoint.path());
+        let mut request = self.default_request(endpoint.method(), url).await?;
+        // before the cache, which may hold data fetched by a client with more scopes
+        self.check_scope(endpoint)?;
+
+        let cached = self.cache_for(endpoint);
+        if let Some((cache, key, _)) = &cached {
+            // a broken cache only costs a request
+            if let Ok(Some(entry)) = cache.backend.get(key) {
+                if !entry.expired() {
+                    if let Ok(data) = serde_json::from_slice(&entry.body) {
+                        trace::record_cache_hit();
+                        return Ok(data);
+                    }
+                }
+            }
+        }
+
+        request = request.query(&endpoint.query());
+        if let Some(body) = endpoint.body() {
+            request = request.json(&body);
+        }
+        let resp = self.send(request, endpoint.group()).await?;
+        let resp = error_for_status_with_text(resp).await?;
+        let Some((cache, key, ttl)) = cached else {
+            let data = resp.json().await?;
+            return Ok(data);
+        };
+        let body = resp.bytes().await?;
+        let data = serde_json::from_slice(&body)?;
+        let _ = cache.backend.set(&key, CacheEntry::new(body.to_vec(), ttl));
         Ok(data)
     }
 
+    /// fail with `Error::MissingScope` if the access token wasn't granted the
+    /// scope the endpoint needs, rather than sending a request bound to fail
+    fn check_scope<E: Endpoint>(&self, endpoint: &E) -> Result<(), Error> {
+        let (Some(scope), Some(token)) = (endpoint.scope(), self.access_token()) else {
+            return Ok(());
+        };
+        if token.has_scope(&scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingScope {
+                scope,
+                endpoint: endpoint.name().to_string(),
+            })
+        }
+    }
+
+    /// the cache, key and TTL of an endpoint, if it is cached
+    fn cache_for<E: Endpoint>(&self, endpoint: &E) -> Option<(&Cache, String, Duration)> {
+        let cache = self.inner.cache.as_ref()?;
+        if endpoint.method() != Method::GET {
+            return None;
+        }
+        let ttl = cache.ttl_of(endpoint.name())?;
+        Some((cache, self.cache_key(endpoint), ttl))
+    }
+
+    fn cache_key<E: Endpoint>(&self, endpoint: &E) -> String {
+        let query = endpoint
+            .query()
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        format!(
+            "{} {}{}?{query}",
+            self.inner.id,
+            self.inner.host.url(),
+            endpoint.path()
+        )
+    }
+
+    /// drop the cached response of an endpoint, so the next call fetches it
+    pub fn invalidate<E: Endpoint>(&self, endpoint: &E) -> Result<(), Error> {
+        if let Some(cache) = &self.inner.cache {
+            cache
+                .backend
+                .remove(&self.cache_key(endpoint))
+                .map_err(Error::Cache)?;
+        }
+        Ok(())
+    }
+
+    /// drop every cached response
+    pub fn clear_cache(&self) -> Result<(), Error> {
+        if let Some(cache) = &self.inner.cache {
+            cache.backend.clear().map_err(Error::Cache)?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_accounts(&self) -> Result<Response<Accounts>, Error> {
+        self.execute(&GetAccounts).await
+    }
+
     pub async fn get_account_balance(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
     ) -> Result<Response<AccountBalance>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/accounts/{}/balance",
-            self.host.url(),
-            account_id.into()
-        );
-
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-        let data = resp.json().await?;
-
-        Ok(data)
+        let endpoint = GetAccountBalance {
+            account_id: account_id.into(),
+        };
+        self.execute(&endpoint).await
     }
 
+    /// first page of transactions, see `get_account_transactions_page` for the others
+    /// and `account_transactions` to walk all of them
     pub async fn get_account_transactions(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
         from_date: Option<NaiveDate>,
         to_date: Option<NaiveDate>,
         transaction_type: Option<TransactionType>,
     ) -> Result<Response<Transactions>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/accounts/{}/transactions",
-            self.host.url(),
-            account_id.into()
-        );
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .query(&[("toDate", to_date), ("fromDate", from_date)])
-            .query(&[("transactionType", transaction_type)])
-            .send()
-            .await?
-            .error_for_status()?;
-        let data = resp.json().await?;
+        let endpoint = GetAccountTransactions {
+            account_id: account_id.into(),
+            from_date,
+            to_date,
+            transaction_type,
+            page: None,
+        };
+        self.execute(&endpoint).await
+    }
 
-        Ok(data)
+    /// a specific page of transactions, starting at 1. `meta.total_pages` on
+    /// the response tells how many there are.
+    pub async fn get_account_transactions_page(
+        &self,
+        account_id: impl Into<String>,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+        page: usize,
+    ) -> Result<Response<Transactions>, Error> {
+        let endpoint = GetAccountTransactions {
+            account_id: account_id.into(),
+            from_date,
+            to_date,
+            transaction_type,
+            page: Some(page),
+        };
+        self.execute(&endpoint).await
     }
 
-    pub async fn get_profiles(&mut self) -> Result<Response<Vec<Profile>>, Error> {
-        let url = format!("{}/za/pb/v1/profiles", self.host.url(),);
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
+    /// stream every transaction matching the filters, fetching pages as needed
+    pub fn account_transactions(
+        &self,
+        account_id: impl Into<String>,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+    ) -> impl Stream<Item = Result<Transaction, Error>> + 'static {
+        self.transaction_stream(account_id.into(), from_date, to_date, transaction_type)
+    }
 
-        let data = resp.json().await?;
-        Ok(data)
+    // not generic over the account id, so the stream doesn't borrow it
+    fn transaction_stream(
+        &self,
+        account_id: String,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        transaction_type: Option<TransactionType>,
+    ) -> impl Stream<Item = Result<Transaction, Error>> + 'static {
+        let state = TransactionPages {
+            client: self.clone(),
+            account_id,
+            transaction_type,
+            next_page: Some(1),
+            buffer: VecDeque::new(),
+        };
+        stream::try_unfold(state, move |mut state| async move {
+            loop {
+                if let Some(transaction) = state.buffer.pop_front() {
+                    return Ok(Some((transaction, state)));
+                }
+                let Some(page) = state.next_page else {
+                    return Ok(None);
+                };
+                let resp = state
+                    .client
+                    .get_account_transactions_page(
+                        &state.account_id,
+                        from_date,
+                        to_date,
+                        state.transaction_type.clone(),
+                        page,
+                    )
+                    .await?;
+                state.next_page = (page < resp.meta.total_pages).then_some(page + 1);
+                state.buffer.extend(resp.data.transactions);
+            }
+        })
+    }
+
+    pub async fn get_profiles(&self) -> Result<Response<Vec<Profile>>, Error> {
+        self.execute(&GetProfiles).await
     }
 
     pub async fn get_profile_accounts(
-        &mut self,
+        &self,
         profile_id: impl Into<String>,
     ) -> Result<Response<Vec<Account>>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/profiles/{}/accounts",
-            self.host.url(),
-            profile_id.into()
-        );
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let data = resp.json().await?;
-        Ok(data)
+        let endpoint = GetProfileAccounts {
+            profile_id: profile_id.into(),
+        };
+        self.execute(&endpoint).await
     }
 
-    // TODO!: define struct for response data
-    // not sure what all the possiblities are yet
     pub async fn get_auth_setup_details(
-        &mut self,
+        &self,
         profile_id: impl Into<String>,
         account_id: impl Into<String>,
     ) -> Result<Response<serde_json::Value>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/profiles/{}/accounts/{}/authorisationsetupdetails",
-            self.host.url(),
-            profile_id.into(),
-            account_id.into()
-        );
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let data = resp.json().await?;
-        Ok(data)
+        let endpoint = GetAuthSetupDetails {
+            profile_id: profile_id.into(),
+            account_id: account_id.into(),
+        };
+        self.execute(&endpoint).await
     }
 
     // TODO!: figure out why this is returning 404
     pub async fn get_profile_beneficiaries(
-        &mut self,
+        &self,
         profile_id: impl Into<String>,
         account_id: impl Into<String>,
     ) -> Result<Response<Vec<Beneficiary>>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/profiles/{}/beneficiaries/{}",
-            self.host.url(),
-            profile_id.into(),
-            account_id.into()
-        );
-
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let data = resp.json().await?;
-        Ok(data)
+        let endpoint = GetProfileBeneficiaries {
+            profile_id: profile_id.into(),
+            account_id: account_id.into(),
+        };
+        self.execute(&endpoint).await
     }
 
-    pub async fn get_beneficiaries(&mut self) -> Result<Response<Vec<Beneficiary>>, Error> {
-        let url = format!("{}/za/pb/v1/accounts/beneficiaries", self.host.url(),);
-        let resp = self
-            .default_request(Method::GET, url)
-            .await?
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let data = resp.json().await?;
-        Ok(data)
+    pub async fn get_beneficiaries(&self) -> Result<Response<Vec<Beneficiary>>, Error> {
+        self.execute(&GetBeneficiaries).await
     }
 
     pub async fn transfer_multiple(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
         transfer_list: MultiTransferRequest,
     ) -> Result<Response<MultiTransferResponse>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/accounts/{}/transfermultiple",
-            self.host.url(),
-            account_id.into()
-        );
-        let resp = self
-            .default_request(Method::POST, url)
-            .await?
-            .json(&transfer_list)
-            .send()
-            .await?;
-        let resp = error_for_status_with_text(resp).await?;
-
-        let data = resp.json().await?;
-        Ok(data)
+        let endpoint = TransferMultiple {
+            account_id: account_id.into(),
+            transfers: transfer_list,
+        };
+        self.execute(&endpoint).await
     }
 
     pub async fn transfer_single(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
         request: Transfer,
         profile_id: impl Into<Option<String>>,
@@ -312,42 +768,25 @@ impl Client {
     }
 
     pub async fn get_beneficiary_categories(
-        &mut self,
+        &self,
     ) -> Result<Response<Vec<BeneficiaryCategory>>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/accounts/beneficiarycategories",
-            self.host.url()
-        );
-        let resp = self.default_request(Method::GET, url).await?.send().await?;
-        let resp = error_for_status_with_text(resp).await?;
-        let data = resp.json().await?;
-        Ok(data)
+        self.execute(&GetBeneficiaryCategories).await
     }
 
     pub async fn pay_multiple(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
         payment_list: MutliPaymentRequest,
     ) -> Result<Response<MultiPaymentResponse>, Error> {
-        let url = format!(
-            "{}/za/pb/v1/accounts/{}/paymultiple",
-            self.host.url(),
-            account_id.into()
-        );
-        let resp = self
-            .default_request(Method::POST, url)
-            .await?
-            .json(&payment_list)
-            .send()
-            .await?;
-        // TODO! handle specific api errors
-        let resp = error_for_status_with_text(resp).await?;
-        let data = resp.json().await?;
-        Ok(data)
+        let endpoint = PayMultiple {
+            account_id: account_id.into(),
+            payments: payment_list,
+        };
+        self.execute(&endpoint).await
     }
 
     pub async fn pay_single(
-        &mut self,
+        &self,
         account_id: impl Into<String>,
         payment: Payment,
     ) -> Result<SinglePaymentResponse, Error> {
@@ -361,39 +800,173 @@ impl Client {
         };
         Ok(data)
     }
+    /// Call an endpoint this crate doesn't wrap yet, with the same token refresh,
+    /// retries and error handling as the others. `path` is relative to the host,
+    /// e.g. `/za/pb/v1/accounts/{accountId}/pending-transactions`, and the
+    /// response is expected in the usual `data`, `links`, `meta` envelope.
+    ///
+    /// ```no_run
+    /// # async fn run(client: investec::client::Client) -> Result<(), investec::Error> {
+    /// use investec::client::Method;
+    ///
+    /// let cards: investec::response::Response<serde_json::Value> = client
+    ///     .request(Method::GET, "/za/v1/cards", &[], None::<&()>)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request<T: DeserializeOwned, B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&B>,
+    ) -> Result<Response<T>, Error> {
+        let body = match body {
+            Some(body) => Some(serde_json::to_value(body).map_err(Error::RequestBody)?),
+            None => None,
+        };
+        let endpoint = Raw {
+            method,
+            path,
+            query,
+            body,
+            response: PhantomData::<T>,
+        };
+        self.execute(&endpoint).await
+    }
+}
+
+struct TransactionPages {
+    client: Client,
+    account_id: String,
+    transaction_type: Option<TransactionType>,
+    next_page: Option<usize>,
+    buffer: VecDeque<Transaction>,
 }
 
 async fn error_for_status_with_text(resp: reqwest::Response) -> Result<reqwest::Response, Error> {
     let status = resp.status();
     if status.is_client_error() || status.is_server_error() {
-        let body = resp.text().await?;
-        Err(Error::CustomRequest(body))
+        let message = resp.text().await?;
+        Err(Error::Api { status, message })
     } else {
         Ok(resp)
     }
 }
 
+/// endpoint behind [`Client::request`]
+struct Raw<'a, T> {
+    method: Method,
+    path: &'a str,
+    query: &'a [(&'a str, &'a str)],
+    body: Option<serde_json::Value>,
+    response: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Endpoint for Raw<'_, T> {
+    type Response = Response<T>;
+
+    fn name(&self) -> &'static str {
+        "request"
+    }
+
+    fn method(&self) -> Method {
+        self.method.clone()
+    }
+
+    // the path may contain ids
+    fn path_template(&self) -> &'static str {
+        "(custom)"
+    }
+
+    fn path(&self) -> String {
+        format!("/{}", self.path.trim_start_matches('/'))
+    }
+
+    fn query(&self) -> Vec<(&str, String)> {
+        self.query
+            .iter()
+            .map(|(k, v)| (*k, v.to_string()))
+            .collect()
+    }
+
+    fn body(&self) -> Option<serde_json::Value> {
+        self.body.clone()
+    }
+}
+
+/// read the whole body and rebuild the response around it, so middleware
+/// can see the body and callers can still consume it
+#[cfg(not(target_arch = "wasm32"))]
+async fn buffer_response(
+    resp: reqwest::Response,
+) -> Result<(reqwest::Response, bytes::Bytes), Error> {
+    use reqwest::ResponseBuilderExt;
+
+    let mut builder = http::Response::builder()
+        .status(resp.status())
+        .version(resp.version())
+        .url(resp.url().clone());
+    if let Some(headers) = builder.headers_mut() {
+        *headers = resp.headers().clone();
+    }
+    let body = resp.bytes().await?;
+    let resp = builder
+        .body(body.clone())
+        .map_err(|e| Error::CustomRequest(e.to_string()))?;
+    Ok((resp.into(), body))
+}
+
+#[derive(Debug, Clone)]
 pub enum Host {
     Live,
     Sandbox,
+    /// any other base url, e.g. a local `mock::MockServer`
+    Custom(Url),
 }
 
 impl Host {
-    fn url(&self) -> String {
+    pub(crate) fn url(&self) -> String {
         match self {
             Self::Live => "https://openapi.investec.com".to_string(),
             Self::Sandbox => "https://openapisandbox.investec.com".to_string(),
+            Self::Custom(url) => url.as_str().trim_end_matches('/').to_string(),
         }
     }
 }
 
+/// builds the token store once the host and client id are known
+type TokenStoreFactory = Box<dyn FnOnce(&TokenKey) -> Box<dyn AsyncTokenStore> + Send>;
+
 pub struct ClientBuilder {
     id: Option<String>,
     secret: Option<String>,
     key: Option<String>,
     host: Option<Host>,
-    token_store: Option<Box<dyn TokenStore>>,
+    token_store: Option<TokenStoreFactory>,
     refresh_auth: Option<bool>,
+    token_leeway: Option<Duration>,
+    scopes: Vec<Scope>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limits: HashMap<EndpointGroup, RateLimit>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    cache: Option<Cache>,
+    http_client: Option<reqwest::Client>,
+    #[cfg(not(target_arch = "wasm32"))]
+    proxies: Vec<reqwest::Proxy>,
+    #[cfg(not(target_arch = "wasm32"))]
+    root_certificates: Vec<reqwest::Certificate>,
+    #[cfg(not(target_arch = "wasm32"))]
+    timeout: Option<Duration>,
+    #[cfg(not(target_arch = "wasm32"))]
+    connect_timeout: Option<Duration>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClientBuilder {
@@ -405,6 +978,21 @@ impl ClientBuilder {
             host: None,
             token_store: None,
             refresh_auth: None,
+            token_leeway: None,
+            scopes: Vec::new(),
+            retry_policy: None,
+            rate_limits: HashMap::new(),
+            middleware: Vec::new(),
+            cache: None,
+            http_client: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            proxies: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            root_certificates: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            timeout: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            connect_timeout: None,
         }
     }
 
@@ -419,18 +1007,54 @@ impl ClientBuilder {
             field: "key".to_string(),
         })?;
 
-        let refresh_auth = *&self.refresh_auth.unwrap_or(false);
+        let refresh_auth = self.refresh_auth.unwrap_or(false);
 
         let host = self.host.unwrap_or(Host::Live);
+
+        let token_key = TokenKey::new(&host, &id).with_scopes(self.scopes.clone());
+        let token_store = self.token_store.map(|store| store(&token_key));
+
+        let http_client = match self.http_client {
+            Some(http_client) => http_client,
+            #[cfg(target_arch = "wasm32")]
+            None => reqwest::Client::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => {
+                let mut builder = reqwest::Client::builder();
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+                for cert in self.root_certificates {
+                    builder = builder.add_root_certificate(cert);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                builder.build()?
+            }
+        };
+
         let client = Client {
-            id,
-            secret,
-            key,
-            host,
-            access_token: None,
-            token_store: self.token_store,
-            refresh_auth,
-            http_client: reqwest::Client::new(),
+            inner: Arc::new(ClientRef {
+                id,
+                secret,
+                key,
+                host,
+                access_token: RwLock::new(None),
+                refresh_lock: tokio::sync::Mutex::new(()),
+                token_store,
+                refresh_auth,
+                token_leeway: self.token_leeway.unwrap_or(DEFAULT_TOKEN_LEEWAY),
+                scopes: self.scopes,
+                retry_policy: self.retry_policy,
+                rate_limiter: RateLimiter::new(self.rate_limits),
+                middleware: self.middleware,
+                cache: self.cache,
+                http_client,
+            }),
         };
         Ok(client)
     }
@@ -455,14 +1079,22 @@ impl ClientBuilder {
         self
     }
 
-    pub fn token_store<T: TokenStore + 'static>(mut self, store: T) -> Self {
-        self.token_store = Some(Box::new(store));
+    pub fn host(mut self, host: Host) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// keep the token in a store: a [`token::TokenStore`], an
+    /// [`AsyncTokenStore`] or a [`token::KeyedTokenStore`] shared by clients
+    pub fn token_store<T: IntoTokenStore + Send + 'static>(mut self, store: T) -> Self {
+        self.token_store = Some(Box::new(move |key| store.into_token_store(key)));
         self
     }
 
-    /// to set local file store for token
+    /// to set local file store for token (Web Storage in the browser), one per
+    /// host and client id, see [`token::FileStore::for_key`]
     pub fn local_token(mut self) -> Self {
-        self.token_store = Some(Box::new(FileStore::default()));
+        self.token_store = Some(Box::new(token::local_store));
         self
     }
 
@@ -470,4 +1102,78 @@ impl ClientBuilder {
         self.refresh_auth = Some(true);
         self
     }
+
+    /// request tokens for these scopes only, e.g. just `Accounts` and
+    /// `Transactions` for a read-only job. All the client is allowed by default
+    pub fn scopes(mut self, scopes: impl IntoIterator<Item = Scope>) -> Self {
+        self.scopes = scopes.into_iter().collect();
+        self
+    }
+
+    /// treat tokens as expired this long before `expires_at`,
+    /// [`DEFAULT_TOKEN_LEEWAY`] by default
+    pub fn token_leeway(mut self, leeway: Duration) -> Self {
+        self.token_leeway = Some(leeway);
+        self
+    }
+
+    /// retry transient failures of idempotent requests, off by default
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// limit the requests sent to a group of endpoints, including retries.
+    /// The limit is shared by all clones of the client.
+    pub fn rate_limit(mut self, group: EndpointGroup, limit: RateLimit) -> Self {
+        self.rate_limits.insert(group, limit);
+        self
+    }
+
+    /// run every request through a middleware, after those already added
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// serve slow-changing endpoints from a cache, see [`crate::cache`].
+    /// The cache is shared by all clones of the client.
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// use a preconfigured http client.
+    /// Proxy, certificate and timeout settings on this builder are then ignored.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// route requests through a proxy, can be called more than once
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// trust an additional root certificate, e.g. for a TLS intercepting proxy
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_root_certificate(mut self, cert: reqwest::Certificate) -> Self {
+        self.root_certificates.push(cert);
+        self
+    }
+
+    /// timeout for the whole request, from connecting until the body is read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
 }
@@ -0,0 +1,266 @@
+//! Client settings from a TOML file with named profiles.
+//!
+//! ```toml
+//! # .env file to load before reading credentials, relative to this file
+//! env_file = ".env"
+//!
+//! [profiles.household]
+//! # credentials can be given directly or as the name of an environment variable
+//! client_id_env = "HOUSEHOLD_CLIENT_ID"
+//! client_secret_env = "HOUSEHOLD_CLIENT_SECRET"
+//! api_key_env = "HOUSEHOLD_API_KEY"
+//! token_file = "household-token.json"
+//! refresh_auth = true
+//! token_leeway_secs = 60
+//! # request tokens for these scopes only
+//! scopes = ["accounts", "balances", "transactions"]
+//! timeout_secs = 30
+//!
+//! [profiles.household.retry]
+//! max_retries = 3
+//! initial_backoff_ms = 500
+//!
+//! [profiles.sandbox]
+//! environment = "sandbox"
+//! client_id = "..."
+//! client_secret = "..."
+//! api_key = "..."
+//! ```
+//!
+//! Load a profile with [`ClientBuilder::from_config`], or all of them with
+//! [`ClientRegistry::from_config`]. Credentials missing from a profile are
+//! reported by `build` as [`Error::ClientFieldUndefined`].
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    client::{ClientBuilder, Host, Url},
+    registry::ClientRegistry,
+    retry::RetryPolicy,
+    token::{FileStore, Scope},
+    Error,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// `.env` file loaded before reading credentials from the environment.
+    /// Variables already set take precedence.
+    pub env_file: Option<PathBuf>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    // directory of the config file, relative paths are resolved against it
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub client_id: Option<String>,
+    pub client_id_env: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_secret_env: Option<String>,
+    pub api_key: Option<String>,
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub environment: Environment,
+    /// base url used instead of `environment`, e.g. for a local mock
+    pub host: Option<String>,
+    /// file to cache the access token in
+    pub token_file: Option<PathBuf>,
+    #[serde(default)]
+    pub refresh_auth: bool,
+    /// see [`ClientBuilder::token_leeway`]
+    pub token_leeway_secs: Option<u64>,
+    /// see [`ClientBuilder::scopes`]
+    pub scopes: Option<Vec<String>>,
+    pub timeout_secs: Option<u64>,
+    pub connect_timeout_secs: Option<u64>,
+    pub retry: Option<RetryConfig>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Live,
+    Sandbox,
+}
+
+/// [`RetryPolicy`] settings, unset ones keep their default
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: Option<u32>,
+    pub initial_backoff_ms: Option<u64>,
+    pub max_backoff_ms: Option<u64>,
+    pub max_retry_after_secs: Option<u64>,
+    pub multiplier: Option<f64>,
+    pub jitter: Option<bool>,
+}
+
+impl Config {
+    /// read and parse a config file, loading its `env_file` if it has one
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let body = std::fs::read_to_string(path)?;
+        let mut config: Config =
+            toml::from_str(&body).map_err(|e| Error::Config(format!("{}: {e}", path.display())))?;
+        config.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        if let Some(env_file) = &config.env_file {
+            let env_file = config.dir.join(env_file);
+            dotenvy::from_path(&env_file)
+                .map_err(|e| Error::Config(format!("{}: {e}", env_file.display())))?;
+        }
+        Ok(config)
+    }
+
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// a builder with the settings of a profile
+    pub fn builder(&self, profile: &str) -> Result<ClientBuilder, Error> {
+        let settings = self
+            .profiles
+            .get(profile)
+            .ok_or_else(|| Error::Config(format!("profile {profile} not found")))?;
+        settings.builder(&self.dir)
+    }
+}
+
+impl ProfileConfig {
+    fn builder(&self, dir: &Path) -> Result<ClientBuilder, Error> {
+        let mut builder = ClientBuilder::new();
+        if let Some(id) = value(&self.client_id, &self.client_id_env) {
+            builder = builder.id(id);
+        }
+        if let Some(secret) = value(&self.client_secret, &self.client_secret_env) {
+            builder = builder.secret(secret);
+        }
+        if let Some(key) = value(&self.api_key, &self.api_key_env) {
+            builder = builder.key(key);
+        }
+
+        let host = match (&self.host, self.environment) {
+            (Some(url), _) => {
+                let url = Url::parse(url).map_err(|e| Error::Config(format!("host {url}: {e}")))?;
+                Host::Custom(url)
+            }
+            (None, Environment::Live) => Host::Live,
+            (None, Environment::Sandbox) => Host::Sandbox,
+        };
+        builder = builder.host(host);
+
+        if let Some(token_file) = &self.token_file {
+            builder = builder.token_store(FileStore::new(dir.join(token_file)));
+        }
+        if self.refresh_auth {
+            builder = builder.refresh_auth();
+        }
+        if let Some(scopes) = &self.scopes {
+            let scopes = scopes
+                .iter()
+                // unknown scopes in a config file are more likely typos than new ones
+                .map(|scope| match Scope::from(scope.as_str()) {
+                    Scope::Unknown(_) => Err(Error::Config(format!("unknown scope {scope}"))),
+                    scope => Ok(scope),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            builder = builder.scopes(scopes);
+        }
+        if let Some(secs) = self.token_leeway_secs {
+            builder = builder.token_leeway(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(retry) = &self.retry {
+            builder = builder.retry_policy(retry.policy()?);
+        }
+        Ok(builder)
+    }
+}
+
+impl RetryConfig {
+    /// the policy, or `Error::Config` for settings that make no sense
+    pub fn policy(&self) -> Result<RetryPolicy, Error> {
+        if let Some(multiplier) = self.multiplier {
+            if !multiplier.is_finite() || multiplier < 1.0 {
+                return Err(Error::Config(format!(
+                    "retry multiplier {multiplier} must be at least 1"
+                )));
+            }
+        }
+        if let (Some(initial), Some(max)) = (self.initial_backoff_ms, self.max_backoff_ms) {
+            if initial > max {
+                return Err(Error::Config(format!(
+                    "initial_backoff_ms {initial} is more than max_backoff_ms {max}"
+                )));
+            }
+        }
+
+        let mut policy = RetryPolicy::default();
+        if let Some(max_retries) = self.max_retries {
+            policy = policy.max_retries(max_retries);
+        }
+        if let Some(ms) = self.initial_backoff_ms {
+            policy = policy.initial_backoff(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.max_backoff_ms {
+            policy = policy.max_backoff(Duration::from_millis(ms));
+        }
+        if let Some(secs) = self.max_retry_after_secs {
+            policy = policy.max_retry_after(Duration::from_secs(secs));
+        }
+        if let Some(multiplier) = self.multiplier {
+            policy = policy.multiplier(multiplier);
+        }
+        if let Some(jitter) = self.jitter {
+            policy = policy.jitter(jitter);
+        }
+        Ok(policy)
+    }
+}
+
+/// the value itself, or else the environment variable it points to
+fn value(value: &Option<String>, env: &Option<String>) -> Option<String> {
+    value.clone().or_else(|| std::env::var(env.as_ref()?).ok())
+}
+
+impl ClientBuilder {
+    /// settings of a profile in a TOML config file, see [`crate::config`]
+    pub fn from_config(path: impl AsRef<Path>, profile: &str) -> Result<Self, Error> {
+        Config::load(path)?.builder(profile)
+    }
+
+    /// [`crate::client::Client::from_env`], after loading a `.env` file from
+    /// the current directory or its parents if there is one
+    pub fn from_dotenv() -> Self {
+        let _ = dotenvy::dotenv();
+        crate::client::Client::from_env()
+    }
+}
+
+impl ClientRegistry {
+    /// a client for every profile in a TOML config file
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let config = Config::load(path)?;
+        let mut registry = Self::new();
+        for name in config.profile_names() {
+            registry.register(name, config.builder(name)?)?;
+        }
+        Ok(registry)
+    }
+}
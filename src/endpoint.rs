@@ -0,0 +1,405 @@
+//! Declarative descriptions of API endpoints.
+//!
+//! Every [`Client`](crate::client::Client) method is an [`Endpoint`] run by
+//! [`Client::execute`](crate::client::Client::execute), which takes care of
+//! tokens, retries, rate limits, tracing and errors. Endpoints this crate
+//! doesn't wrap can be described the same way:
+//!
+//! ```no_run
+//! use investec::{client::Method, endpoint::Endpoint, response::Response};
+//!
+//! struct PendingTransactions {
+//!     account_id: String,
+//! }
+//!
+//! impl Endpoint for PendingTransactions {
+//!     type Response = Response<serde_json::Value>;
+//!
+//!     fn path_template(&self) -> &'static str {
+//!         "/za/pb/v1/accounts/{accountId}/pending-transactions"
+//!     }
+//!
+//!     fn path(&self) -> String {
+//!         format!("/za/pb/v1/accounts/{}/pending-transactions", self.account_id)
+//!     }
+//! }
+//!
+//! # async fn run(client: investec::client::Client) -> Result<(), investec::Error> {
+//! let endpoint = PendingTransactions { account_id: "1234".to_string() };
+//! let pending = client.execute(&endpoint).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::NaiveDate;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    rate_limit::EndpointGroup,
+    request::{MultiTransferRequest, MutliPaymentRequest},
+    response::{
+        Account, AccountBalance, Accounts, Beneficiary, BeneficiaryCategory, MultiPaymentResponse,
+        MultiTransferResponse, Profile, Response, TransactionType, Transactions,
+    },
+    token::Scope,
+};
+
+/// An API endpoint: how to call it and what it returns
+pub trait Endpoint {
+    /// the json the endpoint responds with
+    type Response: DeserializeOwned;
+
+    /// name of the endpoint in traces, the type name by default
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    /// path with placeholders instead of ids, recorded in traces in place of `path`
+    fn path_template(&self) -> &'static str;
+
+    /// path relative to the host, starting with `/`
+    fn path(&self) -> String;
+
+    fn query(&self) -> Vec<(&str, String)> {
+        Vec::new()
+    }
+
+    /// json body, `None` for requests without one
+    fn body(&self) -> Option<Value> {
+        None
+    }
+
+    /// scope the access token needs, checked before the request is sent.
+    /// `None` to leave it to the API
+    fn scope(&self) -> Option<Scope> {
+        None
+    }
+
+    /// rate limit the endpoint counts against, by default guessed from the path
+    fn group(&self) -> EndpointGroup {
+        EndpointGroup::of_path(&self.path())
+    }
+}
+
+pub struct GetAccounts;
+
+impl Endpoint for GetAccounts {
+    type Response = Response<Accounts>;
+
+    fn name(&self) -> &'static str {
+        "get_accounts"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Accounts)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts"
+    }
+
+    fn path(&self) -> String {
+        self.path_template().to_string()
+    }
+}
+
+pub struct GetAccountBalance {
+    pub account_id: String,
+}
+
+impl Endpoint for GetAccountBalance {
+    type Response = Response<AccountBalance>;
+
+    fn name(&self) -> &'static str {
+        "get_account_balance"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Balances)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/{accountId}/balance"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/accounts/{}/balance", self.account_id)
+    }
+}
+
+/// one page of an account's transactions, optionally filtered
+pub struct GetAccountTransactions {
+    pub account_id: String,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+    pub transaction_type: Option<TransactionType>,
+    /// 1 based, the API returns the first page if not set
+    pub page: Option<usize>,
+}
+
+impl Endpoint for GetAccountTransactions {
+    type Response = Response<Transactions>;
+
+    fn name(&self) -> &'static str {
+        "get_account_transactions"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Transactions)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/{accountId}/transactions"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/accounts/{}/transactions", self.account_id)
+    }
+
+    fn query(&self) -> Vec<(&str, String)> {
+        let mut query = Vec::new();
+        if let Some(to_date) = self.to_date {
+            query.push(("toDate", to_date.to_string()));
+        }
+        if let Some(from_date) = self.from_date {
+            query.push(("fromDate", from_date.to_string()));
+        }
+        if let Some(transaction_type) = &self.transaction_type {
+            if let Value::String(t) = json!(transaction_type) {
+                query.push(("transactionType", t));
+            }
+        }
+        if let Some(page) = self.page {
+            query.push(("page", page.to_string()));
+        }
+        query
+    }
+}
+
+pub struct GetProfiles;
+
+impl Endpoint for GetProfiles {
+    type Response = Response<Vec<Profile>>;
+
+    fn name(&self) -> &'static str {
+        "get_profiles"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Accounts)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/profiles"
+    }
+
+    fn path(&self) -> String {
+        self.path_template().to_string()
+    }
+}
+
+pub struct GetProfileAccounts {
+    pub profile_id: String,
+}
+
+impl Endpoint for GetProfileAccounts {
+    type Response = Response<Vec<Account>>;
+
+    fn name(&self) -> &'static str {
+        "get_profile_accounts"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Accounts)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/profiles/{profileId}/accounts"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/profiles/{}/accounts", self.profile_id)
+    }
+}
+
+pub struct GetAuthSetupDetails {
+    pub profile_id: String,
+    pub account_id: String,
+}
+
+impl Endpoint for GetAuthSetupDetails {
+    // TODO!: define struct for response data
+    // not sure what all the possiblities are yet
+    type Response = Response<Value>;
+
+    fn name(&self) -> &'static str {
+        "get_auth_setup_details"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Beneficiarypayments)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/profiles/{profileId}/accounts/{accountId}/authorisationsetupdetails"
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "/za/pb/v1/profiles/{}/accounts/{}/authorisationsetupdetails",
+            self.profile_id, self.account_id
+        )
+    }
+}
+
+pub struct GetProfileBeneficiaries {
+    pub profile_id: String,
+    pub account_id: String,
+}
+
+impl Endpoint for GetProfileBeneficiaries {
+    type Response = Response<Vec<Beneficiary>>;
+
+    fn name(&self) -> &'static str {
+        "get_profile_beneficiaries"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Beneficiarypayments)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/profiles/{profileId}/beneficiaries/{accountId}"
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "/za/pb/v1/profiles/{}/beneficiaries/{}",
+            self.profile_id, self.account_id
+        )
+    }
+}
+
+pub struct GetBeneficiaries;
+
+impl Endpoint for GetBeneficiaries {
+    type Response = Response<Vec<Beneficiary>>;
+
+    fn name(&self) -> &'static str {
+        "get_beneficiaries"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Beneficiarypayments)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/beneficiaries"
+    }
+
+    fn path(&self) -> String {
+        self.path_template().to_string()
+    }
+}
+
+pub struct GetBeneficiaryCategories;
+
+impl Endpoint for GetBeneficiaryCategories {
+    type Response = Response<Vec<BeneficiaryCategory>>;
+
+    fn name(&self) -> &'static str {
+        "get_beneficiary_categories"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Beneficiarypayments)
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/beneficiarycategories"
+    }
+
+    fn path(&self) -> String {
+        self.path_template().to_string()
+    }
+}
+
+pub struct TransferMultiple {
+    pub account_id: String,
+    pub transfers: MultiTransferRequest,
+}
+
+impl Endpoint for TransferMultiple {
+    type Response = Response<MultiTransferResponse>;
+
+    fn name(&self) -> &'static str {
+        "transfer_multiple"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Transfers)
+    }
+
+    fn group(&self) -> EndpointGroup {
+        EndpointGroup::Payments
+    }
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/{accountId}/transfermultiple"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/accounts/{}/transfermultiple", self.account_id)
+    }
+
+    fn body(&self) -> Option<Value> {
+        Some(json!(self.transfers))
+    }
+}
+
+pub struct PayMultiple {
+    pub account_id: String,
+    pub payments: MutliPaymentRequest,
+}
+
+impl Endpoint for PayMultiple {
+    type Response = Response<MultiPaymentResponse>;
+
+    fn name(&self) -> &'static str {
+        "pay_multiple"
+    }
+
+    fn scope(&self) -> Option<Scope> {
+        Some(Scope::Beneficiarypayments)
+    }
+
+    fn group(&self) -> EndpointGroup {
+        EndpointGroup::Payments
+    }
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/{accountId}/paymultiple"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/accounts/{}/paymultiple", self.account_id)
+    }
+
+    fn body(&self) -> Option<Value> {
+        Some(json!(self.payments))
+    }
+}
@@ -1,7 +1,23 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
+#[cfg(all(any(test, feature = "mock"), not(target_arch = "wasm32")))]
+pub mod cassette;
 pub mod client;
+#[cfg(all(feature = "config", not(target_arch = "wasm32")))]
+pub mod config;
+pub mod endpoint;
+pub mod meta;
+pub mod middleware;
+#[cfg(all(any(test, feature = "mock"), not(target_arch = "wasm32")))]
+pub mod mock;
+pub mod rate_limit;
+pub mod registry;
 pub mod request;
 pub mod response;
+pub mod retry;
 pub mod token;
+mod trace;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -11,6 +27,9 @@ pub enum Error {
     #[error("Token io error: {0}")]
     TokenIo(#[from] anyhow::Error),
 
+    #[error(transparent)]
+    TokenStore(#[from] token::TokenStoreError),
+
     #[error("Client field not defined: {field}")]
     ClientFieldUndefined { field: String },
 
@@ -23,9 +42,36 @@ pub enum Error {
     #[error("Access Token not set")]
     NoAccessToken,
 
+    #[error("Access token lacks the {scope} scope needed by {endpoint}")]
+    MissingScope {
+        scope: token::Scope,
+        endpoint: String,
+    },
+
     #[error("Request error: {0}")]
     CustomRequest(String),
+
+    #[error("Invalid request body: {0}")]
+    RequestBody(#[source] serde_json::Error),
+
+    #[error("Api error {status}: {message}")]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+
+    #[error("Config error: {0}")]
+    Config(String),
+
+    #[error("Io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Cache error: {0}")]
+    Cache(anyhow::Error),
+
+    #[error("Invalid response body: {0}")]
+    Decode(#[from] serde_json::Error),
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests;
@@ -0,0 +1,122 @@
+//! HTTP details of a response, for when the deserialized body isn't enough.
+//!
+//! Wrap any client call in [`ResponseMeta::capture`] to get the status,
+//! headers, request id, latency and body length of the response it got,
+//! whether the call succeeded or not:
+//!
+//! ```no_run
+//! # async fn run(client: investec::client::Client) {
+//! use investec::meta::ResponseMeta;
+//!
+//! let (accounts, meta) = ResponseMeta::capture(client.get_accounts()).await;
+//! if let (Err(e), Some(meta)) = (&accounts, &meta) {
+//!     eprintln!("{e}, request id {:?}", meta.request_id);
+//! }
+//! # }
+//! ```
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use reqwest::{header::HeaderMap, StatusCode};
+
+/// headers that may identify a request to Investec support, in order of preference
+const REQUEST_ID_HEADERS: [&str; 4] = [
+    "x-request-id",
+    "x-correlation-id",
+    "x-amzn-requestid",
+    "request-id",
+];
+
+/// Status, headers and timing of a response
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// id assigned to the request by the API, if it sent one
+    pub request_id: Option<String>,
+    /// time from sending the request until the response was received
+    pub elapsed: Duration,
+    /// length of the raw body, when known
+    pub body_len: Option<usize>,
+}
+
+type Slot = Arc<Mutex<Option<ResponseMeta>>>;
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Slot>> = const { RefCell::new(None) };
+}
+
+/// makes `slot` the capture target while polling, restoring the outer one after
+struct Scope(Option<Slot>);
+
+impl Scope {
+    fn enter(slot: &Slot) -> Self {
+        Self(CAPTURE.with(|c| c.replace(Some(slot.clone()))))
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CAPTURE.with(|c| *c.borrow_mut() = self.0.take());
+    }
+}
+
+impl ResponseMeta {
+    /// Run a client call and return its output with the details of the last
+    /// response it received. Token requests and retries count as responses too,
+    /// the endpoint's own response comes last.
+    pub async fn capture<F: Future>(future: F) -> (F::Output, Option<ResponseMeta>) {
+        let slot = Slot::default();
+        let mut future = std::pin::pin!(future);
+        let output = std::future::poll_fn(|cx| {
+            let _scope = Scope::enter(&slot);
+            future.as_mut().poll(cx)
+        })
+        .await;
+        let meta = slot.lock().unwrap().take();
+        (output, meta)
+    }
+
+    /// [`ResponseMeta::capture`] for calls of the blocking client
+    #[cfg(feature = "blocking")]
+    pub fn capture_blocking<T>(call: impl FnOnce() -> T) -> (T, Option<ResponseMeta>) {
+        let slot = Slot::default();
+        let output = {
+            let _scope = Scope::enter(&slot);
+            call()
+        };
+        let meta = slot.lock().unwrap().take();
+        (output, meta)
+    }
+}
+
+pub(crate) fn is_capturing() -> bool {
+    CAPTURE.with(|c| c.borrow().is_some())
+}
+
+pub(crate) fn record(resp: &reqwest::Response, elapsed: Duration, body: Option<&[u8]>) {
+    let Some(slot) = CAPTURE.with(|c| c.borrow().clone()) else {
+        return;
+    };
+    let headers = resp.headers();
+    let request_id = REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok())
+        .map(str::to_string);
+    let body_len = match body {
+        Some(body) => Some(body.len()),
+        None => resp.content_length().map(|len| len as usize),
+    };
+    *slot.lock().unwrap() = Some(ResponseMeta {
+        status: resp.status(),
+        headers: headers.clone(),
+        request_id,
+        elapsed,
+        body_len,
+    });
+}
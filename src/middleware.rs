@@ -0,0 +1,43 @@
+//! Hooks around every http request the client sends.
+//!
+//! A [`Middleware`] registered with [`crate::client::ClientBuilder::middleware`]
+//! sees each attempt, including token requests, retries and replays after a
+//! token refresh. Use it for logging, metrics, audit trails or extra headers.
+//!
+//! ```
+//! use investec::{middleware::Middleware, Error};
+//! use reqwest::header::HeaderValue;
+//!
+//! struct CorrelationId(HeaderValue);
+//!
+//! impl Middleware for CorrelationId {
+//!     fn on_request(&self, request: &mut reqwest::Request) -> Result<(), Error> {
+//!         request.headers_mut().insert("x-correlation-id", self.0.clone());
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use reqwest::{Request, Response};
+
+use crate::Error;
+
+/// Inspect or modify outgoing requests and observe their outcome.
+///
+/// Middlewares run in the order they were registered. Token requests carry the
+/// client credentials in their `Authorization` header, other requests the
+/// bearer token, so redact headers before logging them.
+pub trait Middleware: Send + Sync {
+    /// called before a request is sent, returning an error aborts it
+    fn on_request(&self, _request: &mut Request) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// called when a response arrives, before its status is checked.
+    /// `body` is the raw response body, it is `None` on wasm where responses
+    /// can't be buffered and rebuilt.
+    fn on_response(&self, _request: &Request, _response: &Response, _body: Option<&[u8]>) {}
+
+    /// called when a request failed without a response
+    fn on_error(&self, _request: &Request, _error: &reqwest::Error) {}
+}
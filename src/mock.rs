@@ -0,0 +1,729 @@
+//! An in-process stand-in for the Investec API.
+//!
+//! [`MockServer`] listens on a random local port and emulates the token, account
+//! information, transfer and payment endpoints using configurable [`Fixtures`],
+//! so tests and local development don't need network access to the sandbox.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), investec::Error> {
+//! let server = investec::mock::MockServer::start();
+//! let client = server.client();
+//! let accounts = client.get_accounts().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use base64::Engine;
+use chrono::NaiveDate;
+use hyper::{
+    header::{HeaderMap, AUTHORIZATION, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::{
+    client::{
+        Client, ClientBuilder, Host, SANDBOX_API_KEY, SANDBOX_CLIENT_ID, SANDBOX_CLIENT_SECRET,
+    },
+    token::FileStore,
+};
+
+pub const MOCK_ACCOUNT: &str = "3353431574710163189587446";
+pub const MOCK_PROFILE: &str = "10163189587444";
+
+/// Data served by the [`MockServer`].
+///
+/// API models are kept as raw json, in the same shape Investec returns them,
+/// so fixtures can also exercise fields the response types don't know about.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    pub client_id: String,
+    pub client_secret: String,
+    pub api_key: String,
+    /// space separated, as returned by the token endpoint
+    pub scope: String,
+    pub expires_in: u32,
+    pub profiles: Vec<Value>,
+    pub accounts: Vec<Value>,
+    /// matched on `accountId`
+    pub balances: Vec<Value>,
+    /// matched on `accountId`
+    pub transactions: Vec<Value>,
+    /// transactions per page
+    pub page_size: usize,
+    pub beneficiaries: Vec<Value>,
+    pub beneficiary_categories: Vec<Value>,
+    pub auth_setup_details: Value,
+}
+
+impl Default for Fixtures {
+    /// Sandbox credentials and a small data set modelled on the Investec sandbox
+    fn default() -> Self {
+        Self {
+            client_id: SANDBOX_CLIENT_ID.to_string(),
+            client_secret: SANDBOX_CLIENT_SECRET.to_string(),
+            api_key: SANDBOX_API_KEY.to_string(),
+            scope: "accounts balances transactions transfers beneficiarypayments".to_string(),
+            expires_in: 1799,
+            profiles: vec![json!({
+                "profileId": MOCK_PROFILE,
+                "profileName": "Joe Soap",
+                "defaultProfile": true,
+            })],
+            accounts: vec![
+                json!({
+                    "accountId": MOCK_ACCOUNT,
+                    "accountNumber": "10011001001",
+                    "accountName": "Mr J Soap",
+                    "referenceName": "Mr J Soap",
+                    "productName": "Private Bank Account",
+                    "kycCompliant": true,
+                    "profileId": MOCK_PROFILE,
+                    "profileName": "Joe Soap",
+                }),
+                json!({
+                    "accountId": "3353431574710163189587447",
+                    "accountNumber": "10011001002",
+                    "accountName": "Mr J Soap",
+                    "referenceName": "Savings",
+                    "productName": "PrimeSaver",
+                    "kycCompliant": true,
+                    "profileId": MOCK_PROFILE,
+                    "profileName": "Joe Soap",
+                }),
+            ],
+            balances: vec![
+                json!({
+                    "accountId": MOCK_ACCOUNT,
+                    "currentBalance": 28857.76,
+                    "availableBalance": 98857.76,
+                    "budgetBalance": 0.0,
+                    "straightBalance": 0.0,
+                    "cashBalance": 28857.76,
+                    "currency": "ZAR",
+                }),
+                json!({
+                    "accountId": "3353431574710163189587447",
+                    "currentBalance": 12000.0,
+                    "availableBalance": 12000.0,
+                    "budgetBalance": null,
+                    "straightBalance": null,
+                    "cashBalance": null,
+                    "currency": "ZAR",
+                }),
+            ],
+            transactions: vec![
+                transaction("2023-10-01", "CardPurchases", "DEBIT", "WOOLWORTHS", 120.5),
+                transaction("2023-10-02", "CardPurchases", "DEBIT", "CHECKERS", 340.0),
+                transaction(
+                    "2023-10-02",
+                    "FeesAndInterest",
+                    "DEBIT",
+                    "MONTHLY FEE",
+                    55.0,
+                ),
+                transaction("2023-10-03", "Deposits", "CREDIT", "SALARY", 25000.0),
+                transaction("2023-10-05", "CardPurchases", "DEBIT", "UBER", 98.2),
+            ],
+            page_size: 100,
+            beneficiaries: vec![
+                json!({
+                    "beneficiaryId": "MTAxODIwMzUwNTA2ODAjMQ==",
+                    "accountNumber": "10012345678",
+                    "code": "580105",
+                    "bank": "INVESTEC BANK LIMITED",
+                    "beneficiaryName": "Jane Soap",
+                    "lastPaymentAmount": "100.00",
+                    "lastPaymentDate": "01/09/2023",
+                    "cellNo": null,
+                    "emailAddress": null,
+                    "name": "Jane Soap",
+                    "referenceAccountNumber": "Joe",
+                    "referenceName": "Jane",
+                    "categoryId": "10182035050680",
+                    "profileId": MOCK_PROFILE,
+                    "fasterPaymentAllowed": true,
+                }),
+                json!({
+                    "beneficiaryId": "MTAxODIwMzUwNTA2ODAjMg==",
+                    "accountNumber": "62012345678",
+                    "code": "250655",
+                    "bank": "FNB",
+                    "beneficiaryName": null,
+                    "lastPaymentAmount": null,
+                    "lastPaymentDate": null,
+                    "cellNo": null,
+                    "emailAddress": null,
+                    "name": "Landlord",
+                    "referenceAccountNumber": "Rent",
+                    "referenceName": null,
+                    "categoryId": "10182035050680",
+                    "profileId": MOCK_PROFILE,
+                    "fasterPaymentAllowed": false,
+                }),
+            ],
+            beneficiary_categories: vec![json!({
+                "CategoryId": "10182035050680",
+                "DefaultCategory": "true",
+                "CategoryName": "Default",
+            })],
+            auth_setup_details: json!([{
+                "numberOfAuthorisationRequired": 0,
+                "accountId": MOCK_ACCOUNT,
+            }]),
+        }
+    }
+}
+
+fn transaction(
+    date: &str,
+    transaction_type: &str,
+    dt_ct: &str,
+    description: &str,
+    amount: f32,
+) -> Value {
+    json!({
+        "accountId": MOCK_ACCOUNT,
+        "type": dt_ct,
+        "transactionType": transaction_type,
+        "status": "POSTED",
+        "description": description,
+        "cardNumber": "",
+        "postedOrder": 0,
+        "postingDate": date,
+        "valueDate": date,
+        "actionDate": date,
+        "transactionDate": date,
+        "amount": amount,
+        "runningBalance": 0.0,
+    })
+}
+
+struct State {
+    fixtures: Fixtures,
+    base: Url,
+    tokens: Mutex<HashSet<String>>,
+    token_requests: AtomicUsize,
+    references: AtomicUsize,
+    requests: Mutex<Vec<(String, HeaderMap)>>,
+    failures: Mutex<VecDeque<(StatusCode, Option<u64>)>>,
+}
+
+/// A local HTTP server emulating the Investec API.
+///
+/// The server runs on its own thread and runtime, so it can be used from any
+/// async runtime (or none). It shuts down when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a server with the default fixtures
+    pub fn start() -> Self {
+        Self::with_fixtures(Fixtures::default())
+    }
+
+    /// Start a server serving the given fixtures
+    pub fn with_fixtures(fixtures: Fixtures) -> Self {
+        let (listener, addr) = bind();
+        let state = Arc::new(State {
+            fixtures,
+            base: Url::parse(&format!("http://{addr}")).expect("mock server url"),
+            tokens: Mutex::new(HashSet::new()),
+            token_requests: AtomicUsize::new(0),
+            references: AtomicUsize::new(0),
+            requests: Mutex::new(Vec::new()),
+            failures: Mutex::new(VecDeque::new()),
+        });
+        let (shutdown, thread) = serve(listener, state.clone(), handle);
+
+        let server = Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        };
+        let _ = std::fs::remove_file(server.token_path());
+        server
+    }
+
+    pub fn url(&self) -> Url {
+        self.state.base.clone()
+    }
+
+    pub fn host(&self) -> Host {
+        Host::Custom(self.url())
+    }
+
+    pub fn fixtures(&self) -> &Fixtures {
+        &self.state.fixtures
+    }
+
+    /// Number of times the token endpoint has been called
+    pub fn token_requests(&self) -> usize {
+        self.state.token_requests.load(Ordering::SeqCst)
+    }
+
+    /// Invalidate every token issued so far, as if Investec revoked them
+    pub fn revoke_tokens(&self) {
+        self.state.tokens.lock().unwrap().clear();
+    }
+
+    /// Every request received so far, as `"METHOD /path"`.
+    /// Responses carry the request's position in this list as `x-request-id: mock-request-{n}`.
+    pub fn requests(&self) -> Vec<String> {
+        let requests = self.state.requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|(request, _)| request.clone())
+            .collect()
+    }
+
+    /// Value of header `name` on every request received so far, in the same
+    /// order as [`MockServer::requests`]
+    pub fn header_values(&self, name: &str) -> Vec<Option<String>> {
+        let requests = self.state.requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|(_, headers)| {
+                let value = headers.get(name)?.to_str().ok()?;
+                Some(value.to_string())
+            })
+            .collect()
+    }
+
+    /// Answer the next `times` requests with `status` instead of serving them,
+    /// optionally with a `Retry-After` header in seconds
+    pub fn fail_next(&self, times: usize, status: StatusCode, retry_after: Option<u64>) {
+        let mut failures = self.state.failures.lock().unwrap();
+        failures.extend(std::iter::repeat_n((status, retry_after), times));
+    }
+
+    /// Location of the token file used by clients from [`MockServer::client`].
+    /// Removed when the server is dropped.
+    pub fn token_path(&self) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "investec-mock-{}-{}.json",
+            std::process::id(),
+            self.addr.port()
+        ))
+    }
+
+    /// A client pointing to this server with the fixture credentials, a token
+    /// store private to this server and auto refresh enabled.
+    /// Equivalent of `Client::sandbox()`.
+    pub fn client(&self) -> Client {
+        self.builder()
+            .token_store(FileStore::new(self.token_path()))
+            .refresh_auth()
+            .build()
+            .expect("mock client")
+    }
+
+    /// A builder with the fixture credentials and host already set
+    pub fn builder(&self) -> ClientBuilder {
+        ClientBuilder::new()
+            .id(self.state.fixtures.client_id.clone())
+            .secret(self.state.fixtures.client_secret.clone())
+            .key(self.state.fixtures.api_key.clone())
+            .host(self.host())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_file(self.token_path());
+    }
+}
+
+/// a listener on a random local port
+pub(crate) fn bind() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+    listener
+        .set_nonblocking(true)
+        .expect("set mock server non-blocking");
+    let addr = listener.local_addr().expect("mock server address");
+    (listener, addr)
+}
+
+/// serve requests with `handler` on a thread of its own, until the returned
+/// sender is used or dropped
+pub(crate) fn serve<S, F>(
+    listener: TcpListener,
+    state: Arc<S>,
+    handler: fn(Arc<S>, Request<Body>) -> F,
+) -> (oneshot::Sender<()>, JoinHandle<()>)
+where
+    S: Send + Sync + 'static,
+    F: Future<Output = Result<MockResponse, Infallible>> + Send + 'static,
+{
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("mock server runtime");
+        runtime.block_on(async move {
+            let make_service = make_service_fn(move |_| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| handler(state.clone(), req))) }
+            });
+            let server = Server::from_tcp(listener)
+                .expect("mock server listener")
+                .serve(make_service);
+            tokio::select! {
+                _ = server => {}
+                _ = shutdown_rx => {}
+            }
+        });
+    });
+    (shutdown, thread)
+}
+
+pub(crate) type MockResponse = Response<Body>;
+
+async fn handle(state: Arc<State>, req: Request<Body>) -> Result<MockResponse, Infallible> {
+    let request_id = {
+        let mut requests = state.requests.lock().unwrap();
+        requests.push((
+            format!("{} {}", req.method(), req.uri().path()),
+            req.headers().clone(),
+        ));
+        format!("mock-request-{}", requests.len())
+    };
+    let failure = state.failures.lock().unwrap().pop_front();
+    let mut resp = if let Some((status, retry_after)) = failure {
+        let mut resp = json_response(status, json!({ "message": "injected failure" }));
+        if let Some(seconds) = retry_after {
+            resp.headers_mut()
+                .insert(RETRY_AFTER, seconds.to_string().parse().unwrap());
+        }
+        resp
+    } else {
+        match route(&state, req).await {
+            Ok(resp) => resp,
+            Err(Rejection(status, message)) => json_response(status, json!({ "message": message })),
+        }
+    };
+    resp.headers_mut()
+        .insert("x-request-id", request_id.parse().unwrap());
+    Ok(resp)
+}
+
+async fn route(state: &State, req: Request<Body>) -> Result<MockResponse, Rejection> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+    let path = parts.uri.path().to_string();
+    let query: Vec<(String, String)> = state
+        .base
+        .join(&parts.uri.to_string())
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    if parts.method == Method::POST && segments == ["identity", "v2", "oauth2", "token"] {
+        return state.token(&parts.headers, &body);
+    }
+
+    state.authorize(&parts.headers)?;
+
+    let rest = match segments.as_slice() {
+        ["za", "pb", "v1", rest @ ..] => rest,
+        _ => return Err(not_found()),
+    };
+    match (&parts.method, rest) {
+        (&Method::GET, ["accounts"]) => {
+            Ok(state.envelope(&path, json!({ "accounts": state.fixtures.accounts })))
+        }
+        (&Method::GET, ["accounts", "beneficiaries"]) => {
+            Ok(state.envelope(&path, json!(state.fixtures.beneficiaries)))
+        }
+        (&Method::GET, ["accounts", "beneficiarycategories"]) => {
+            Ok(state.envelope(&path, json!(state.fixtures.beneficiary_categories)))
+        }
+        (&Method::GET, ["accounts", account_id, "balance"]) => {
+            let balance = find(&state.fixtures.balances, "accountId", account_id)?;
+            Ok(state.envelope(&path, balance.clone()))
+        }
+        (&Method::GET, ["accounts", account_id, "transactions"]) => {
+            state.account(account_id)?;
+            let transactions = state.transactions(account_id, &query)?;
+            let page_size = state.fixtures.page_size.max(1);
+            let total_pages = transactions.len().div_ceil(page_size).max(1);
+            let page = match query.iter().find(|(k, _)| k == "page") {
+                Some((_, page)) => page
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|p| *p >= 1)
+                    .ok_or_else(|| error(StatusCode::BAD_REQUEST, "invalid page"))?,
+                None => 1,
+            };
+            let transactions: Vec<_> = transactions
+                .into_iter()
+                .skip((page - 1) * page_size)
+                .take(page_size)
+                .collect();
+            Ok(state.page(&path, json!({ "transactions": transactions }), total_pages))
+        }
+        (&Method::POST, ["accounts", account_id, "transfermultiple"]) => {
+            state.account(account_id)?;
+            let data = state.transfer_multiple(&body)?;
+            Ok(state.envelope(&path, data))
+        }
+        (&Method::POST, ["accounts", account_id, "paymultiple"]) => {
+            state.account(account_id)?;
+            let data = state.pay_multiple(&body)?;
+            Ok(state.envelope(&path, data))
+        }
+        (&Method::GET, ["profiles"]) => Ok(state.envelope(&path, json!(state.fixtures.profiles))),
+        (&Method::GET, ["profiles", profile_id, "accounts"]) => {
+            find(&state.fixtures.profiles, "profileId", profile_id)?;
+            let accounts = filter(&state.fixtures.accounts, "profileId", profile_id);
+            Ok(state.envelope(&path, json!(accounts)))
+        }
+        (
+            &Method::GET,
+            ["profiles", profile_id, "accounts", account_id, "authorisationsetupdetails"],
+        ) => {
+            find(&state.fixtures.profiles, "profileId", profile_id)?;
+            state.account(account_id)?;
+            Ok(state.envelope(&path, state.fixtures.auth_setup_details.clone()))
+        }
+        (&Method::GET, ["profiles", profile_id, "beneficiaries", account_id]) => {
+            find(&state.fixtures.profiles, "profileId", profile_id)?;
+            state.account(account_id)?;
+            let beneficiaries = filter(&state.fixtures.beneficiaries, "profileId", profile_id);
+            Ok(state.envelope(&path, json!(beneficiaries)))
+        }
+        _ => Err(not_found()),
+    }
+}
+
+impl State {
+    fn token(&self, headers: &hyper::HeaderMap, body: &[u8]) -> Result<MockResponse, Rejection> {
+        self.token_requests.fetch_add(1, Ordering::SeqCst);
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!(
+            "{}:{}",
+            self.fixtures.client_id, self.fixtures.client_secret
+        ));
+        let basic = header(headers, AUTHORIZATION.as_str());
+        let key = header(headers, "x-api-key");
+        if basic != Some(&format!("Basic {credentials}")) || key != Some(&self.fixtures.api_key) {
+            return Err(error(StatusCode::UNAUTHORIZED, "invalid_client"));
+        }
+
+        // grant the requested scopes the fixtures allow, or all of them
+        let form = format!("?{}", String::from_utf8_lossy(body));
+        let requested = self.base.join(&form).ok().and_then(|url| {
+            url.query_pairs()
+                .find(|(name, _)| name == "scope")
+                .map(|(_, scope)| scope.into_owned())
+        });
+        let scope = match requested {
+            Some(requested) => requested
+                .split_whitespace()
+                .filter(|scope| self.fixtures.scope.split_whitespace().any(|s| s == *scope))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => self.fixtures.scope.clone(),
+        };
+
+        let n = self.references.fetch_add(1, Ordering::SeqCst);
+        let access_token = format!("mock-{}-{}", self.base.port().unwrap_or_default(), n);
+        self.tokens.lock().unwrap().insert(access_token.clone());
+        Ok(json_response(
+            StatusCode::OK,
+            json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": self.fixtures.expires_in,
+                "scope": scope,
+            }),
+        ))
+    }
+
+    fn authorize(&self, headers: &hyper::HeaderMap) -> Result<(), Rejection> {
+        let token = header(headers, AUTHORIZATION.as_str())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "missing bearer token"))?;
+        if self.tokens.lock().unwrap().contains(token) {
+            Ok(())
+        } else {
+            Err(error(StatusCode::UNAUTHORIZED, "invalid bearer token"))
+        }
+    }
+
+    fn envelope(&self, path: &str, data: Value) -> MockResponse {
+        self.page(path, data, 1)
+    }
+
+    fn page(&self, path: &str, data: Value, total_pages: usize) -> MockResponse {
+        let self_ = self.base.join(path).map(String::from).unwrap_or_default();
+        json_response(
+            StatusCode::OK,
+            json!({
+                "data": data,
+                "links": { "self": self_ },
+                "meta": { "totalPages": total_pages },
+            }),
+        )
+    }
+
+    fn account(&self, account_id: &str) -> Result<&Value, Rejection> {
+        find(&self.fixtures.accounts, "accountId", account_id)
+    }
+
+    fn transactions(
+        &self,
+        account_id: &str,
+        query: &[(String, String)],
+    ) -> Result<Vec<Value>, Rejection> {
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(k, v)| k == name && !v.is_empty())
+                .map(|(_, v)| v.as_str())
+        };
+        let date = |name: &str| -> Result<Option<NaiveDate>, Rejection> {
+            param(name)
+                .map(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d"))
+                .transpose()
+                .map_err(|_| error(StatusCode::BAD_REQUEST, &format!("invalid {name}")))
+        };
+        let from_date = date("fromDate")?;
+        let to_date = date("toDate")?;
+        let transaction_type = param("transactionType");
+
+        let transactions = filter(&self.fixtures.transactions, "accountId", account_id)
+            .into_iter()
+            .filter(|t| {
+                let posted = t["transactionDate"]
+                    .as_str()
+                    .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+                from_date.is_none_or(|from| posted.is_some_and(|d| d >= from))
+                    && to_date.is_none_or(|to| posted.is_some_and(|d| d <= to))
+                    && transaction_type.is_none_or(|tt| t["transactionType"] == tt)
+            })
+            .collect();
+        Ok(transactions)
+    }
+
+    fn transfer_multiple(&self, body: &[u8]) -> Result<Value, Rejection> {
+        let body: Value = serde_json::from_slice(body)
+            .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        let transfers = body["transferList"]
+            .as_array()
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "transferList is required"))?;
+        let responses = transfers
+            .iter()
+            .map(|t| {
+                let account_id = t["beneficiaryAccountId"].as_str().unwrap_or_default();
+                let account = self.account(account_id)?;
+                Ok(self.transfer_response(account_id, &account["referenceName"]))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(json!({ "TransferResponses": responses, "ErrorMessage": null }))
+    }
+
+    fn pay_multiple(&self, body: &[u8]) -> Result<Value, Rejection> {
+        let body: Value = serde_json::from_slice(body)
+            .map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        let payments = body["paymentList"]
+            .as_array()
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "paymentList is required"))?;
+        let responses = payments
+            .iter()
+            .map(|p| {
+                let beneficiary_id = p["beneficiaryId"].as_str().unwrap_or_default();
+                let beneficiary = find(
+                    &self.fixtures.beneficiaries,
+                    "beneficiaryId",
+                    beneficiary_id,
+                )?;
+                Ok(self.transfer_response(beneficiary_id, &beneficiary["name"]))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(json!({ "TransferResponses": responses, "ErrorMessage": null }))
+    }
+
+    fn transfer_response(&self, beneficiary_account_id: &str, beneficiary_name: &Value) -> Value {
+        let n = self.references.fetch_add(1, Ordering::SeqCst);
+        let today = chrono::Utc::now()
+            .date_naive()
+            .format("%d/%m/%Y")
+            .to_string();
+        json!({
+            "PaymentReferenceNumber": format!("MOCK{n:08}"),
+            "PaymentDate": today,
+            "Status": format!("- No authorisation necessary <BR> - Payment/Transfer effective date {today}"),
+            "BeneficiaryName": beneficiary_name,
+            "BeneficiaryAccountId": beneficiary_account_id,
+            "AuthorisationRequired": false,
+        })
+    }
+}
+
+fn header<'a>(headers: &'a hyper::HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn find<'a>(items: &'a [Value], key: &str, value: &str) -> Result<&'a Value, Rejection> {
+    items
+        .iter()
+        .find(|item| item[key] == value)
+        .ok_or_else(not_found)
+}
+
+fn filter(items: &[Value], key: &str, value: &str) -> Vec<Value> {
+    items
+        .iter()
+        .filter(|item| item[key] == value)
+        .cloned()
+        .collect()
+}
+
+fn json_response(status: StatusCode, body: Value) -> MockResponse {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("mock response")
+}
+
+/// An error response from the mock API
+struct Rejection(StatusCode, String);
+
+fn error(status: StatusCode, message: &str) -> Rejection {
+    Rejection(status, message.to_string())
+}
+
+fn not_found() -> Rejection {
+    error(StatusCode::NOT_FOUND, "not found")
+}
@@ -0,0 +1,124 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+/// Endpoints that share a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EndpointGroup {
+    /// the oauth token endpoint
+    Auth,
+    /// accounts, balances, transactions, profiles and beneficiaries
+    AccountInfo,
+    /// transfers and payments
+    Payments,
+}
+
+impl EndpointGroup {
+    /// the group of a path relative to the host, as in [`crate::endpoint::Endpoint::path`]
+    pub fn of_path(path: &str) -> Self {
+        if path.starts_with("/identity/") {
+            Self::Auth
+        } else if path.ends_with("/transfermultiple") || path.ends_with("/paymultiple") {
+            Self::Payments
+        } else {
+            Self::AccountInfo
+        }
+    }
+}
+
+/// A token bucket allowing `requests` per `per`, with bursts of up to `burst`
+/// requests (by default `requests`).
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    requests: u32,
+    per: Duration,
+    burst: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self {
+            requests,
+            per,
+            burst: None,
+        }
+    }
+
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// most requests sent at once after a quiet period
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+}
+
+struct Bucket {
+    capacity: f64,
+    // tokens per second
+    rate: f64,
+    // negative when callers are waiting for tokens
+    tokens: f64,
+    // `Instant` isn't available in the browser
+    updated: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        let capacity = limit.burst.unwrap_or(limit.requests).max(1) as f64;
+        Self {
+            capacity,
+            rate: limit.requests.max(1) as f64 / limit.per.as_secs_f64().max(f64::EPSILON),
+            tokens: capacity,
+            updated: Utc::now(),
+        }
+    }
+
+    /// take a token, returning how long to wait before it may be used
+    fn reserve(&mut self) -> Duration {
+        let now = Utc::now();
+        let elapsed = (now - self.updated).to_std().unwrap_or_default();
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.updated = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Rate limits per endpoint group, shared by all clones of a client
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    buckets: HashMap<EndpointGroup, Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: HashMap<EndpointGroup, RateLimit>) -> Self {
+        let buckets = limits
+            .into_iter()
+            .map(|(group, limit)| (group, Mutex::new(Bucket::new(limit))))
+            .collect();
+        Self { buckets }
+    }
+
+    /// wait until a request of `group` is allowed to be sent
+    pub(crate) async fn acquire(&self, group: EndpointGroup) {
+        let Some(bucket) = self.buckets.get(&group) else {
+            return;
+        };
+        let wait = bucket.lock().unwrap().reserve();
+        if !wait.is_zero() {
+            crate::retry::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Several named clients, e.g. one per Investec API key.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::env::var;
+//!
+//! use investec::{client::ClientBuilder, registry::ClientRegistry, token::FileStore};
+//!
+//! let mut registry = ClientRegistry::new();
+//! registry.register(
+//!     "household",
+//!     ClientBuilder::new()
+//!         .id(var("HOUSEHOLD_CLIENT_ID")?)
+//!         .secret(var("HOUSEHOLD_CLIENT_SECRET")?)
+//!         .key(var("HOUSEHOLD_API_KEY")?)
+//!         .token_store(FileStore::new("household-token.json".into())),
+//! )?;
+//! registry.register(
+//!     "business",
+//!     ClientBuilder::new()
+//!         .id(var("BUSINESS_CLIENT_ID")?)
+//!         .secret(var("BUSINESS_CLIENT_SECRET")?)
+//!         .key(var("BUSINESS_API_KEY")?)
+//!         .token_store(FileStore::new("business-token.json".into())),
+//! )?;
+//!
+//! // or, with the `config` feature, a client per profile of a TOML file
+//! // let registry = ClientRegistry::from_config("investec.toml")?;
+//!
+//! for (name, accounts) in registry.get_accounts().await {
+//!     println!("{name}: {:?}", accounts.map(|a| a.data.accounts.len()));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::BTreeMap, future::Future};
+
+use futures_util::future::join_all;
+
+use crate::{
+    client::{Client, ClientBuilder},
+    endpoint::{Endpoint, GetAccounts},
+    response::{Accounts, Response},
+    Error,
+};
+
+/// Clients by name.
+///
+/// Each client keeps its own credentials, host, access token and token store.
+/// Give every client its own store, clients writing to the same file would
+/// overwrite each other's tokens. `local_token` stores are already separate
+/// per host and client id.
+#[derive(Clone, Default)]
+pub struct ClientRegistry {
+    clients: BTreeMap<String, Client>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a client, returning the one previously registered under `name`
+    pub fn insert(&mut self, name: impl Into<String>, client: Client) -> Option<Client> {
+        self.clients.insert(name.into(), client)
+    }
+
+    /// build a client and add it under `name`
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        builder: ClientBuilder,
+    ) -> Result<&Client, Error> {
+        let name = name.into();
+        self.clients.insert(name.clone(), builder.build()?);
+        Ok(&self.clients[&name])
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Client> {
+        self.clients.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Client> {
+        self.clients.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Client)> {
+        self.clients
+            .iter()
+            .map(|(name, client)| (name.as_str(), client))
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// run a call on every client concurrently, with the results by client name
+    pub async fn fan_out<'a, T, F, Fut>(&'a self, call: F) -> BTreeMap<String, Result<T, Error>>
+    where
+        F: Fn(&'a Client) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let calls = self.clients.iter().map(|(name, client)| {
+            let call = call(client);
+            async move { (name.clone(), call.await) }
+        });
+        join_all(calls).await.into_iter().collect()
+    }
+
+    /// [`Client::execute`] on every client
+    pub async fn execute<E: Endpoint>(
+        &self,
+        endpoint: &E,
+    ) -> BTreeMap<String, Result<E::Response, Error>> {
+        self.fan_out(|client| client.execute(endpoint)).await
+    }
+
+    pub async fn get_accounts(&self) -> BTreeMap<String, Result<Response<Accounts>, Error>> {
+        self.execute(&GetAccounts).await
+    }
+}
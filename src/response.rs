@@ -107,11 +107,15 @@ pub struct Links {
     pub self_: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
 pub enum DtCt {
     Debit,
     Credit,
+    /// a value this crate doesn't know yet
+    #[serde(untagged)]
+    Unknown(String),
 }
 
 #[derive(Debug, Deserialize)]
@@ -119,14 +123,18 @@ pub struct Transactions {
     pub transactions: Vec<Transaction>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
 pub enum TransactionStatus {
     Posted,
-    // is this all
+    /// a value this crate doesn't know yet
+    #[serde(untagged)]
+    Unknown(String),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum TransactionType {
     VASTransactions,
     ATMWithdrawals,
@@ -136,6 +144,9 @@ pub enum TransactionType {
     OnlineBankingPayments,
     DebitOrders,
     FasterPay,
+    /// a value this crate doesn't know yet, sent as is in queries
+    #[serde(untagged)]
+    Unknown(String),
 }
 
 #[derive(Debug, Deserialize)]
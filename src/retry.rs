@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+use reqwest::{header::RETRY_AFTER, StatusCode};
+
+/// How the client retries requests that failed for transient reasons
+/// (connection errors, timeouts, 429 and 5xx gateway responses).
+///
+/// Only idempotent requests are retried, so transfers and payments are never
+/// sent twice. Waits grow exponentially from `initial_backoff` up to
+/// `max_backoff`, with jitter, unless the response carries a `Retry-After`.
+/// Responses asking to wait longer than `max_retry_after` are returned as is.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub(crate) max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_retry_after: Duration,
+    multiplier: f64,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// longest `Retry-After` worth waiting for, 60 seconds by default.
+    /// Longer ones give up and return the response
+    pub fn max_retry_after(mut self, wait: Duration) -> Self {
+        self.max_retry_after = wait;
+        self
+    }
+
+    /// how much waits grow per retry, at least 1
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        // `max` also turns NaN into 1
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// randomise waits between half and the full backoff, on by default
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// wait before the given retry (0 based), ignoring `Retry-After`
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64()).max(0.0);
+        let backoff = if self.jitter {
+            backoff * (0.5 + fastrand::f64() * 0.5)
+        } else {
+            backoff
+        };
+        Duration::from_secs_f64(backoff)
+    }
+
+    /// wait before the given retry of `resp`, its `Retry-After` or the
+    /// backoff. `None` if the server asks for more than `max_retry_after`
+    pub(crate) fn wait_for(&self, resp: &reqwest::Response, retry: u32) -> Option<Duration> {
+        match Self::retry_after(resp) {
+            Some(wait) if wait > self.max_retry_after => None,
+            Some(wait) => Some(wait),
+            None => Some(self.backoff(retry)),
+        }
+    }
+
+    pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT
+                | StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    }
+
+    pub(crate) fn is_retryable_error(err: &reqwest::Error) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if err.is_connect() {
+            return true;
+        }
+        err.is_timeout() || err.is_request()
+    }
+
+    /// wait requested by the server, as delay-seconds or an HTTP date
+    pub(crate) fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+        let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+        Some(wait.to_std().unwrap_or_default())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+// there is no tokio timer in the browser
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await
+}
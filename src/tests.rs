@@ -1,77 +1,735 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use chrono::NaiveDate;
+use futures_util::{StreamExt, TryStreamExt};
+use reqwest::{header::HeaderValue, StatusCode};
 
 use crate::{
-    client::Client,
+    cache::{Cache, DiskCache},
+    cassette::{Cassette, Recorder},
+    client::{Client, Host, Method, Url},
+    endpoint::{Endpoint, GetAccountTransactions, GetAccounts},
+    meta::ResponseMeta,
+    middleware::Middleware,
+    mock::{Fixtures, MockServer, MOCK_ACCOUNT, MOCK_PROFILE},
+    rate_limit::{EndpointGroup, RateLimit},
+    registry::ClientRegistry,
     request::{MultiTransferRequest, MutliPaymentRequest, Payment, Transfer},
-    response::TransactionType,
+    response::{
+        Accounts, DtCt, MultiTransferResponse, Response, TransactionStatus, TransactionType,
+        Transactions,
+    },
+    retry::RetryPolicy,
+    token::{AccessToken, FileStore, Scope, TokenKey, TokenStore, TokenStoreError},
+    Error,
 };
 
 #[tokio::test]
 async fn test_get_access_token() {
-    let client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let token = client.get_access_token().await;
     assert!(token.is_ok());
 }
 
+#[tokio::test]
+async fn test_get_access_token_invalid_credentials() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .secret("wrong".to_string())
+        .build()
+        .unwrap();
+    let token = client.get_access_token().await;
+    assert!(token.is_err());
+}
+
+#[tokio::test]
+async fn test_revoked_token_is_rejected() {
+    let server = MockServer::start();
+    // without refresh_auth the caller manages tokens
+    let client = server.builder().build().unwrap();
+    client.authenticate().await.unwrap();
+    server.revoke_tokens();
+    let accounts = client.get_accounts().await;
+    assert!(accounts.is_err());
+}
+
+#[tokio::test]
+async fn test_revoked_token_is_refreshed() {
+    let server = MockServer::start();
+    let client = server.client();
+    client.authenticate().await.unwrap();
+    let token1 = client.access_token().unwrap();
+
+    server.revoke_tokens();
+    let accounts = client.get_accounts().await;
+    assert!(accounts.is_ok());
+    let token2 = client.access_token().unwrap();
+    assert_ne!(token1.access_token, token2.access_token);
+
+    // the store no longer holds the revoked token
+    let stored = FileStore::new(server.token_path()).read().unwrap();
+    assert_eq!(stored.access_token, token2.access_token);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_store_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let server = MockServer::start();
+    let path = server.token_path();
+    // permissions of a previous file are not kept
+    std::fs::write(&path, "{}").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let token = AccessToken {
+        access_token: "token".to_string(),
+        token_type: "Bearer".to_string(),
+        scope: vec![],
+        expires_at: chrono::Utc::now(),
+        issued_for: None,
+    };
+    FileStore::new(path.clone()).write(&token).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // no temporary files left behind
+    let dir = path.parent().unwrap();
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let leftovers = std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            let entry = entry.as_ref().unwrap().file_name();
+            entry.to_string_lossy().starts_with(&format!(".{name}"))
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+}
+
+#[tokio::test]
+async fn test_stale_stored_token_is_replaced() {
+    let server = MockServer::start();
+    let stale = AccessToken {
+        access_token: "stale".to_string(),
+        token_type: "Bearer".to_string(),
+        scope: vec![],
+        expires_at: chrono::Utc::now() + chrono::Duration::minutes(30),
+        issued_for: Some(TokenKey::new(&server.host(), &server.fixtures().client_id)),
+    };
+    FileStore::new(server.token_path()).write(&stale).unwrap();
+
+    let client = server.client();
+    let balance = client.get_account_balance(SANDBOX_ACCOUNT).await;
+    assert!(balance.is_ok());
+    assert_ne!(client.access_token().unwrap().access_token, "stale");
+    assert_eq!(server.token_requests(), 1);
+}
+
+#[tokio::test]
+async fn test_token_for_other_host_is_ignored() {
+    let live = MockServer::start();
+    let sandbox = MockServer::start();
+    let path = live.token_path();
+    let client = live
+        .builder()
+        .token_store(FileStore::new(path.clone()))
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    let stored = FileStore::new(path.clone()).read().unwrap();
+    assert_eq!(stored.issued_for, Some(client.token_key()));
+
+    // same credentials and token file, other host
+    let client = sandbox
+        .builder()
+        .token_store(FileStore::new(path))
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    // a token of its own was fetched before the first call, not after a 401
+    assert_eq!(
+        sandbox.requests(),
+        ["POST /identity/v2/oauth2/token", "GET /za/pb/v1/accounts"]
+    );
+}
+
+#[test]
+fn test_local_store_per_host_and_client() {
+    let live = FileStore::for_key(&TokenKey::new(&Host::Live, "id"));
+    let sandbox = FileStore::for_key(&TokenKey::new(&Host::Sandbox, "id"));
+    let other = FileStore::for_key(&TokenKey::new(&Host::Live, "other/id"));
+    assert_ne!(live.path, sandbox.path);
+    assert_ne!(live.path, other.path);
+    assert_eq!(live.path.parent(), Some(FileStore::dir().as_path()));
+    assert_eq!(
+        other.path.file_name().unwrap(),
+        "openapi.investec.com_other_id.json"
+    );
+}
+
+/// an async store shared by clients, like one in Redis would be
+#[derive(Clone, Default)]
+struct RemoteStore {
+    token: Arc<tokio::sync::Mutex<Option<AccessToken>>>,
+    clears: Arc<Mutex<usize>>,
+}
+
+#[async_trait::async_trait]
+impl crate::token::AsyncTokenStore for RemoteStore {
+    async fn read(&self) -> Result<Option<AccessToken>, TokenStoreError> {
+        tokio::task::yield_now().await;
+        Ok(self.token.lock().await.clone())
+    }
+
+    async fn write(&self, token: &AccessToken) -> Result<(), TokenStoreError> {
+        tokio::task::yield_now().await;
+        *self.token.lock().await = Some(token.clone());
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), TokenStoreError> {
+        *self.clears.lock().unwrap() += 1;
+        *self.token.lock().await = None;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_async_token_store_shared_by_workers() {
+    let server = MockServer::start();
+    let store = RemoteStore::default();
+    let worker = || {
+        server
+            .builder()
+            .token_store(store.clone())
+            .refresh_auth()
+            .build()
+            .unwrap()
+    };
+    let (first, second) = (worker(), worker());
+    assert!(first.get_accounts().await.is_ok());
+    assert!(second.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 1);
+
+    server.revoke_tokens();
+    assert!(second.get_accounts().await.is_ok());
+    assert!(first.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 2);
+    assert_eq!(*store.clears.lock().unwrap(), 1);
+    let stored = store.token.lock().await.clone().unwrap();
+    assert_eq!(
+        stored.access_token,
+        second.access_token().unwrap().access_token
+    );
+    assert_eq!(
+        stored.access_token,
+        first.access_token().unwrap().access_token
+    );
+}
+
+#[tokio::test]
+async fn test_expired_token_refreshed_by_other_worker() {
+    let server = MockServer::start();
+    let store = RemoteStore::default();
+    let worker = || {
+        server
+            .builder()
+            .token_store(store.clone())
+            .refresh_auth()
+            .build()
+            .unwrap()
+    };
+    let (first, second) = (worker(), worker());
+    assert!(first.get_accounts().await.is_ok());
+    assert!(second.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 1);
+
+    let expired = AccessToken {
+        expires_at: chrono::Utc::now() - chrono::Duration::minutes(1),
+        ..first.access_token().unwrap()
+    };
+    first.set_access_token(Some(expired.clone()));
+    second.set_access_token(Some(expired.clone()));
+    *store.token.lock().await = Some(expired);
+
+    assert!(first.get_accounts().await.is_ok());
+    assert!(second.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 2);
+    assert_eq!(
+        second.access_token().unwrap().access_token,
+        first.access_token().unwrap().access_token
+    );
+}
+
+#[tokio::test]
+async fn test_sync_store_as_async_store() {
+    use crate::token::AsyncTokenStore;
+
+    let server = MockServer::start();
+    let store = FileStore::new(server.token_path());
+    assert!(AsyncTokenStore::read(&store).await.unwrap().is_none());
+
+    std::fs::write(&store.path, "not a token").unwrap();
+    let invalid = AsyncTokenStore::read(&store).await;
+    assert!(matches!(invalid, Err(TokenStoreError::Invalid(_))));
+    AsyncTokenStore::clear(&store).await.unwrap();
+    assert!(!store.path.exists());
+}
+
+/// two servers share `store`: each keeps its own token, reused by other
+/// clients of the same server, and expired tokens can be listed and removed
+async fn check_keyed_store<S>(store: S)
+where
+    S: crate::token::KeyedTokenStore + crate::token::IntoTokenStore + Clone + Send + 'static,
+{
+    let (live, sandbox) = (MockServer::start(), MockServer::start());
+    let client = |server: &MockServer| {
+        server
+            .builder()
+            .token_store(store.clone())
+            .refresh_auth()
+            .build()
+            .unwrap()
+    };
+    assert!(client(&live).get_accounts().await.is_ok());
+    assert!(client(&sandbox).get_accounts().await.is_ok());
+    assert!(client(&live).get_accounts().await.is_ok());
+    assert_eq!(live.token_requests(), 1);
+    assert_eq!(sandbox.token_requests(), 1);
+
+    let live_key = TokenKey::new(&live.host(), &live.fixtures().client_id);
+    let sandbox_key = TokenKey::new(&sandbox.host(), &sandbox.fixtures().client_id);
+    let live_token = store.read(&live_key).await.unwrap().unwrap();
+    let sandbox_token = store.read(&sandbox_key).await.unwrap().unwrap();
+    assert_ne!(live_token.access_token, sandbox_token.access_token);
+    assert_eq!(live_token.issued_for, Some(live_key.clone()));
+
+    let soon = chrono::Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(store.expiring_before(soon).await.unwrap().len(), 2);
+    assert!(store
+        .expiring_before(chrono::Utc::now())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let expired = AccessToken {
+        expires_at: chrono::Utc::now() - chrono::Duration::minutes(1),
+        ..live_token
+    };
+    store.write(&live_key, &expired).await.unwrap();
+    let expiring = store.expiring_before(chrono::Utc::now()).await.unwrap();
+    assert_eq!(expiring.len(), 1);
+    assert_eq!(expiring[0].0, live_key);
+    assert_eq!(store.remove_expired().await.unwrap(), 1);
+    assert!(store.read(&live_key).await.unwrap().is_none());
+
+    store.clear(&sandbox_key).await.unwrap();
+    assert!(store.read(&sandbox_key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_memory_store() {
+    check_keyed_store(crate::token::MemoryStore::new()).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn test_sqlite_store() {
+    use crate::token::SqliteStore;
+
+    check_keyed_store(SqliteStore::open_in_memory().unwrap()).await;
+
+    let server = MockServer::start();
+    let path = server.token_path().with_extension("db");
+    let client = server
+        .builder()
+        .token_store(SqliteStore::open(&path).unwrap())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    let reopened = server
+        .builder()
+        .token_store(SqliteStore::open(&path).unwrap())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(reopened.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_unauthorized_refresh_once() {
+    let server = MockServer::start();
+    let client = server.client();
+    client.authenticate().await.unwrap();
+    server.revoke_tokens();
+
+    let handles: Vec<_> = (0..10)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get_account_balance(SANDBOX_ACCOUNT).await })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.await.unwrap().is_ok());
+    }
+    assert_eq!(server.token_requests(), 2);
+}
+
+#[test]
+fn test_token_expires_within() {
+    let token = AccessToken {
+        access_token: "token".to_string(),
+        token_type: "Bearer".to_string(),
+        scope: vec![],
+        expires_at: chrono::Utc::now() + chrono::Duration::seconds(20),
+        issued_for: None,
+    };
+    assert!(!token.expired());
+    assert!(!token.expires_within(Duration::from_secs(10)));
+    assert!(token.expires_within(Duration::from_secs(30)));
+    assert!(token.expires_within(Duration::MAX));
+}
+
+#[tokio::test]
+async fn test_token_leeway() {
+    let server = MockServer::with_fixtures(Fixtures {
+        expires_in: 20,
+        ..Fixtures::default()
+    });
+    // within the default leeway of 30 seconds, so every request gets a new token
+    let client = server.builder().refresh_auth().build().unwrap();
+    client.get_accounts().await.unwrap();
+    client.get_accounts().await.unwrap();
+    assert_eq!(server.token_requests(), 2);
+
+    let client = server
+        .builder()
+        .refresh_auth()
+        .token_leeway(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    client.get_accounts().await.unwrap();
+    client.get_accounts().await.unwrap();
+    assert_eq!(server.token_requests(), 3);
+}
+
+#[tokio::test]
+async fn test_request_scopes() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .scopes([Scope::Accounts, Scope::Balances])
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    let token = client.access_token().unwrap();
+    assert_eq!(token.scope, vec![Scope::Accounts, Scope::Balances]);
+
+    // not sent at all without the scope
+    let requests = server.requests().len();
+    let beneficiaries = client.get_beneficiaries().await;
+    assert!(matches!(
+        beneficiaries,
+        Err(Error::MissingScope { scope: Scope::Beneficiarypayments, endpoint })
+            if endpoint == "get_beneficiaries"
+    ));
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[tokio::test]
+async fn test_scope_checked_before_cache() {
+    let server = MockServer::start();
+    let cache = Cache::memory();
+    let client = |scopes: Vec<Scope>| {
+        server
+            .builder()
+            .scopes(scopes)
+            .cache(cache.clone())
+            .refresh_auth()
+            .build()
+            .unwrap()
+    };
+    let payments = client(vec![Scope::Accounts, Scope::Beneficiarypayments]);
+    assert!(payments.get_beneficiaries().await.is_ok());
+    let read_only = client(vec![Scope::Accounts]);
+    assert!(matches!(
+        read_only.get_beneficiaries().await,
+        Err(Error::MissingScope { .. })
+    ));
+    assert!(read_only.get_profiles().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_scoped_token_not_shared_with_unscoped_client() {
+    let server = MockServer::start();
+    let store = crate::token::MemoryStore::new();
+    let client = |scopes: Vec<Scope>| {
+        server
+            .builder()
+            .scopes(scopes)
+            .token_store(store.clone())
+            .refresh_auth()
+            .build()
+            .unwrap()
+    };
+    let read_only = client(vec![Scope::Balances, Scope::Accounts]);
+    assert!(read_only.get_accounts().await.is_ok());
+    let all = client(vec![]);
+    assert!(all.get_beneficiaries().await.is_ok());
+    assert_eq!(server.token_requests(), 2);
+
+    // the same scopes in another order share the token
+    let same = client(vec![Scope::Accounts, Scope::Balances]);
+    assert!(same.get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 2);
+    assert_eq!(same.token_key(), read_only.token_key());
+}
+
+#[tokio::test]
+async fn test_missing_scope() {
+    let server = MockServer::with_fixtures(Fixtures {
+        scope: "accounts transactions".to_string(),
+        ..Fixtures::default()
+    });
+    let client = server.builder().refresh_auth().build().unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    let balance = client.get_account_balance(MOCK_ACCOUNT).await;
+    assert!(matches!(
+        balance,
+        Err(Error::MissingScope {
+            scope: Scope::Balances,
+            ..
+        })
+    ));
+
+    // a stored token lacking a scope the client asks for is not reused
+    let store = crate::token::MemoryStore::new();
+    let narrow = server.builder().token_store(store.clone()).refresh_auth();
+    assert!(narrow.build().unwrap().get_accounts().await.is_ok());
+    let broad = server
+        .builder()
+        .token_store(store)
+        .scopes([Scope::Accounts, Scope::Balances])
+        .refresh_auth();
+    assert!(broad.build().unwrap().get_accounts().await.is_ok());
+    assert_eq!(server.token_requests(), 3);
+}
+
+#[tokio::test]
+async fn test_background_refresh_ahead_capped() {
+    let server = MockServer::with_fixtures(Fixtures {
+        expires_in: 30,
+        ..Fixtures::default()
+    });
+    let client = server
+        .builder()
+        .refresh_auth()
+        .token_leeway(Duration::ZERO)
+        .build()
+        .unwrap();
+    // longer than tokens last, so refreshes happen at half their lifetime
+    let refresh = client.spawn_token_refresh(Duration::from_secs(60 * 60));
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(server.token_requests(), 1);
+    refresh.abort();
+}
+
+#[tokio::test]
+async fn test_background_refresh_keeps_held_token() {
+    let server = MockServer::with_fixtures(Fixtures {
+        expires_in: 30,
+        ..Fixtures::default()
+    });
+    let client = server
+        .builder()
+        .refresh_auth()
+        .token_leeway(Duration::ZERO)
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+    // the first pass is capped by the token the client holds, so it isn't replaced
+    let refresh = client.spawn_token_refresh(Duration::from_secs(60 * 60));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(server.token_requests(), 1);
+    refresh.abort();
+}
+
+/// wait for the client to hold a token other than `old`, failing after a generous timeout
+async fn wait_for_new_token(client: &Client, old: Option<&AccessToken>) -> AccessToken {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            match client.access_token() {
+                Some(token) if old.is_none_or(|old| old.access_token != token.access_token) => {
+                    return token
+                }
+                _ => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_background_token_refresh() {
+    let server = MockServer::with_fixtures(Fixtures {
+        expires_in: 3,
+        ..Fixtures::default()
+    });
+    let client = server
+        .builder()
+        .refresh_auth()
+        .token_leeway(Duration::ZERO)
+        .build()
+        .unwrap();
+    let refresh = client.spawn_token_refresh(Duration::from_secs(2));
+    let first = wait_for_new_token(&client, None).await;
+    assert_eq!(server.token_requests(), 1);
+
+    // refreshed halfway through its lifetime, as `ahead` is capped, without any request
+    wait_for_new_token(&client, Some(&first)).await;
+    assert_eq!(server.token_requests(), 2);
+
+    client.get_accounts().await.unwrap();
+    assert_eq!(server.token_requests(), 2);
+
+    // the task ends with the client
+    drop(client);
+    tokio::time::timeout(Duration::from_secs(3), refresh)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
 #[tokio::test]
 async fn test_authenticate() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
 
     client.authenticate().await.unwrap();
-    let token1 = client.access_token.clone().unwrap();
+    let token1 = client.access_token().unwrap();
 
     assert!(!token1.expired());
 
     // is it using the same token stored in the client
     client.authenticate().await.unwrap();
-    let token2 = client.access_token.unwrap();
+    let token2 = client.access_token().unwrap();
     assert_eq!(token1.access_token, token2.access_token);
 
     // is it using the same token stored in the file store
-    let mut client = Client::sandbox();
+    let client = server.client();
     client.authenticate().await.unwrap();
-    let mut token3 = client.access_token.clone().unwrap();
+    let mut token3 = client.access_token().unwrap();
     assert_eq!(token1.access_token, token3.access_token);
 
-    // is it getting a new token if expired
+    // is it getting a new token if expired, here and in the file store
     token3.expires_at = chrono::Utc::now();
     assert!(token3.expired());
-    client.access_token = Some(token3);
+    FileStore::new(server.token_path()).write(&token3).unwrap();
+    client.set_access_token(Some(token3));
     client.authenticate().await.unwrap();
-    let token4 = client.access_token.clone().unwrap();
+    let token4 = client.access_token().unwrap();
     assert_ne!(token1.access_token, token4.access_token);
 }
 
-const SANDBOX_ACCOUNT: &'static str = "3353431574710163189587446";
-const SANDBOX_PROFILE: &'static str = "10163189587444";
+#[tokio::test]
+async fn test_concurrent_requests_share_one_token() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    let handles: Vec<_> = (0..30)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get_account_balance(SANDBOX_ACCOUNT).await })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.await.unwrap().is_ok());
+    }
+    assert_eq!(server.token_requests(), 1);
+}
+
+#[test]
+fn test_client_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Client>();
+}
+
+#[tokio::test]
+async fn test_custom_http_client() {
+    let server = MockServer::start();
+    let http_client = reqwest::Client::builder()
+        .user_agent("investec-test")
+        .build()
+        .unwrap();
+    let client = server
+        .builder()
+        .http_client(http_client)
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_proxy() {
+    let server = MockServer::start();
+    // the host is unreachable, so this only succeeds if requests go via the proxy
+    let client = server
+        .builder()
+        .host(Host::Custom(Url::parse("http://investec.invalid").unwrap()))
+        .proxy(reqwest::Proxy::http(server.url()).unwrap())
+        .timeout(Duration::from_secs(10))
+        .connect_timeout(Duration::from_secs(5))
+        .refresh_auth()
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+}
+
+const SANDBOX_ACCOUNT: &str = MOCK_ACCOUNT;
+const SANDBOX_PROFILE: &str = MOCK_PROFILE;
 
 #[tokio::test]
 async fn test_get_accounts() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let accounts = client.get_accounts().await;
     assert!(accounts.is_ok());
 }
 
 #[tokio::test]
 async fn test_get_account_balance() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let balance = client.get_account_balance(SANDBOX_ACCOUNT).await;
     assert!(balance.is_ok());
 }
 
 #[tokio::test]
 async fn test_get_account_transactions() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
 
     let from_date = NaiveDate::from_ymd_opt(2023, 10, 1);
     let to_date = NaiveDate::from_ymd_opt(2023, 10, 3);
     let t_type = TransactionType::CardPurchases;
     let transactions = client
-        .get_account_transactions(SANDBOX_ACCOUNT, from_date, to_date, Some(t_type))
+        .get_account_transactions(SANDBOX_ACCOUNT, from_date, to_date, Some(t_type.clone()))
         .await;
     assert!(transactions.is_ok());
     let transactions = transactions.unwrap();
-    assert!(transactions.data.transactions.len() > 0);
+    assert!(!transactions.data.transactions.is_empty());
 
     for t in transactions.data.transactions {
         assert_eq!(t.transaction_type, t_type);
@@ -80,31 +738,92 @@ async fn test_get_account_transactions() {
     }
 }
 
+#[tokio::test]
+async fn test_unknown_transaction_values() {
+    let mut fixtures = Fixtures::default();
+    let mut unknown = fixtures.transactions[0].clone();
+    unknown["transactionType"] = "CryptoPurchases".into();
+    unknown["status"] = "PENDING".into();
+    unknown["type"] = "REVERSAL".into();
+    fixtures.transactions.push(unknown);
+    let server = MockServer::with_fixtures(fixtures);
+    let client = server.client();
+
+    let all = client
+        .get_account_transactions(SANDBOX_ACCOUNT, None, None, None)
+        .await
+        .unwrap();
+    assert!(all
+        .data
+        .transactions
+        .iter()
+        .any(|t| t.status == TransactionStatus::Posted));
+
+    let crypto = TransactionType::Unknown("CryptoPurchases".to_string());
+    let transactions = client
+        .get_account_transactions(SANDBOX_ACCOUNT, None, None, Some(crypto.clone()))
+        .await
+        .unwrap()
+        .data
+        .transactions;
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].transaction_type, crypto);
+    assert_eq!(
+        transactions[0].status,
+        TransactionStatus::Unknown("PENDING".to_string())
+    );
+    assert_eq!(transactions[0].type_, DtCt::Unknown("REVERSAL".to_string()));
+}
+
+#[tokio::test]
+async fn test_unknown_scope() {
+    let server = MockServer::with_fixtures(Fixtures {
+        scope: "accounts cryptowallets".to_string(),
+        ..Fixtures::default()
+    });
+    let client = server.client();
+    assert!(client.get_accounts().await.is_ok());
+    let token = client.access_token().unwrap();
+    assert_eq!(
+        token.scope,
+        vec![Scope::Accounts, Scope::Unknown("cryptowallets".to_string())]
+    );
+
+    // stored tokens keep it
+    let stored: AccessToken =
+        serde_json::from_str(&serde_json::to_string(&token).unwrap()).unwrap();
+    assert_eq!(stored.scope, token.scope);
+    assert_eq!("cryptowallets".parse(), Ok(token.scope[1].clone()));
+}
+
 #[tokio::test]
 async fn test_get_account_transactions_no_params() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let transactions = client
         .get_account_transactions(SANDBOX_ACCOUNT, None, None, None)
         .await;
     assert!(transactions.is_ok());
     let transactions = transactions.unwrap();
-    assert!(transactions.data.transactions.len() > 0);
+    assert!(!transactions.data.transactions.is_empty());
 }
 
 #[tokio::test]
 async fn test_get_profiles() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let profiles = client.get_profiles().await;
     assert!(profiles.is_ok());
 }
 
 #[tokio::test]
 async fn test_get_profile_accounts() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let resp = client.get_profile_accounts(SANDBOX_PROFILE).await;
     assert!(resp.is_ok());
     let resp = resp.unwrap();
-    assert!(resp.data.len() > 0);
+    assert!(!resp.data.is_empty());
     for a in resp.data {
         assert_eq!(a.profile_id, SANDBOX_PROFILE)
     }
@@ -112,17 +831,19 @@ async fn test_get_profile_accounts() {
 
 #[tokio::test]
 async fn test_get_auth_setup_details() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let resp = client
         .get_auth_setup_details(SANDBOX_PROFILE, SANDBOX_ACCOUNT)
         .await;
     assert!(resp.is_ok());
 }
 
-// this is failing!
+// this is failing against the live sandbox!
 #[tokio::test]
 async fn test_get_profile_beneficiaries() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
 
     // let p_accounts = client.get_profile_accounts("10182035050680").await.unwrap();
     // dbg!(p_accounts);
@@ -139,14 +860,16 @@ async fn test_get_profile_beneficiaries() {
 
 #[tokio::test]
 async fn test_get_beneficiaries() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let resp = client.get_beneficiaries().await;
     assert!(resp.is_ok());
 }
 
 #[tokio::test]
 async fn test_transfer_multiple() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let transfer1 = Transfer::to(SANDBOX_ACCOUNT)
         .amount(10.0)
         .my_reference("test")
@@ -166,9 +889,8 @@ async fn test_transfer_multiple() {
 
 #[tokio::test]
 async fn test_transfer_single() {
-    // wait 2 seconds to avoid hitting burst limits
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let transfer = Transfer::to(SANDBOX_ACCOUNT)
         .amount(10.0)
         .my_reference("test")
@@ -183,14 +905,16 @@ async fn test_transfer_single() {
 
 #[tokio::test]
 async fn test_get_beneficiary_categories() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let resp = client.get_beneficiary_categories().await;
-    assert!(resp.is_ok_and(|d| d.data.len() > 0))
+    assert!(resp.is_ok_and(|d| !d.data.is_empty()))
 }
 
 #[tokio::test]
 async fn test_pay_multiple() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let bens = client.get_beneficiaries().await.unwrap();
     let ben = bens.data.first().unwrap();
     let payment = Payment::to(&ben.beneficiary_id)
@@ -203,12 +927,13 @@ async fn test_pay_multiple() {
         .pay_multiple(SANDBOX_ACCOUNT, MutliPaymentRequest::new(vec![payment]))
         .await;
     assert!(resp.is_ok());
-    assert!(resp.unwrap().data.transfer_responses.len() > 0);
+    assert!(!resp.unwrap().data.transfer_responses.is_empty());
 }
 
 #[tokio::test]
 async fn test_pay_single() {
-    let mut client = Client::sandbox();
+    let server = MockServer::start();
+    let client = server.client();
     let bens = client.get_beneficiaries().await.unwrap();
     let ben = bens.data.last().unwrap();
     let payment = Payment::to(&ben.beneficiary_id)
@@ -220,3 +945,1171 @@ async fn test_pay_single() {
     let resp = client.pay_single(SANDBOX_ACCOUNT, payment).await;
     assert!(resp.is_ok());
 }
+
+#[tokio::test]
+async fn test_raw_request() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    let accounts: Response<Accounts> = client
+        .request(Method::GET, "/za/pb/v1/accounts", &[], None::<&()>)
+        .await
+        .unwrap();
+    assert!(!accounts.data.accounts.is_empty());
+
+    let path = format!("za/pb/v1/accounts/{SANDBOX_ACCOUNT}/transactions");
+    let query = [("transactionType", "CardPurchases")];
+    let transactions: Response<Transactions> = client
+        .request(Method::GET, &path, &query, None::<&()>)
+        .await
+        .unwrap();
+    assert_eq!(transactions.data.transactions.len(), 3);
+}
+
+#[tokio::test]
+async fn test_raw_request_with_body() {
+    let server = MockServer::start();
+    let client = server.client();
+    let transfer = Transfer::to(SANDBOX_ACCOUNT)
+        .amount(10.0)
+        .my_reference("test")
+        .their_reference("test theirs")
+        .build()
+        .unwrap();
+    let body = MultiTransferRequest::new(vec![transfer], None);
+    let path = format!("/za/pb/v1/accounts/{SANDBOX_ACCOUNT}/transfermultiple");
+    let resp: Result<Response<MultiTransferResponse>, _> =
+        client.request(Method::POST, &path, &[], Some(&body)).await;
+    assert!(resp.is_ok_and(|r| r.data.transfer_responses.len() == 1));
+
+    let resp: Result<Response<serde_json::Value>, _> = client
+        .request(Method::GET, "/za/v1/cards", &[], None::<&()>)
+        .await;
+    assert!(matches!(resp, Err(Error::Api { status, .. }) if status == StatusCode::NOT_FOUND));
+
+    // json object keys must be strings, and nothing is sent
+    let requests = server.requests().len();
+    let body = std::collections::BTreeMap::from([(vec![1], 1)]);
+    let resp: Result<Response<serde_json::Value>, _> =
+        client.request(Method::POST, &path, &[], Some(&body)).await;
+    assert!(matches!(resp, Err(Error::RequestBody(_))));
+    assert_eq!(server.requests().len(), requests);
+}
+
+struct PendingTransactions(&'static str);
+
+impl Endpoint for PendingTransactions {
+    type Response = Response<serde_json::Value>;
+
+    fn path_template(&self) -> &'static str {
+        "/za/pb/v1/accounts/{accountId}/pending-transactions"
+    }
+
+    fn path(&self) -> String {
+        format!("/za/pb/v1/accounts/{}/pending-transactions", self.0)
+    }
+}
+
+#[tokio::test]
+async fn test_execute_endpoint() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    let endpoint = GetAccountTransactions {
+        account_id: SANDBOX_ACCOUNT.to_string(),
+        from_date: None,
+        to_date: None,
+        transaction_type: Some(TransactionType::CardPurchases),
+        page: Some(1),
+    };
+    let transactions = client.execute(&endpoint).await.unwrap();
+    assert_eq!(transactions.data.transactions.len(), 3);
+
+    // the mock doesn't know this one, errors look the same for every endpoint
+    let resp = client.execute(&PendingTransactions(SANDBOX_ACCOUNT)).await;
+    assert!(matches!(resp, Err(Error::Api { status, .. }) if status == StatusCode::NOT_FOUND));
+    let resp = client.get_account_balance("unknown").await;
+    assert!(matches!(resp, Err(Error::Api { status, .. }) if status.is_client_error()));
+}
+
+#[tokio::test]
+async fn test_registry_fan_out() {
+    let household = MockServer::start();
+    let business = MockServer::start();
+    let mut registry = ClientRegistry::new();
+    registry.insert("household", household.client());
+    registry
+        .register("business", business.builder().refresh_auth())
+        .unwrap();
+    registry
+        .register(
+            "revoked",
+            household
+                .builder()
+                .secret("wrong".to_string())
+                .refresh_auth(),
+        )
+        .unwrap();
+    assert_eq!(
+        registry.names().collect::<Vec<_>>(),
+        ["business", "household", "revoked"]
+    );
+
+    let accounts = registry.get_accounts().await;
+    assert_eq!(accounts.len(), 3);
+    assert!(accounts["household"].is_ok());
+    assert!(accounts["business"].is_ok());
+    assert!(accounts["revoked"].is_err());
+    assert_eq!(business.token_requests(), 1);
+    // one for the household client and one failed attempt with the wrong secret
+    assert_eq!(household.token_requests(), 2);
+
+    let balances = registry
+        .fan_out(|client| client.get_account_balance(SANDBOX_ACCOUNT))
+        .await;
+    assert!(balances["business"].is_ok());
+    assert_eq!(business.token_requests(), 1);
+}
+
+fn fast_retries() -> RetryPolicy {
+    RetryPolicy::new(3)
+        .initial_backoff(Duration::from_millis(1))
+        .max_backoff(Duration::from_millis(5))
+}
+
+#[tokio::test]
+async fn test_retry_transient_get() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .retry_policy(fast_retries())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+
+    server.fail_next(2, StatusCode::BAD_GATEWAY, None);
+    let accounts = client.get_accounts().await;
+    assert!(accounts.is_ok());
+    let requests = server.requests();
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| *r == "GET /za/pb/v1/accounts")
+            .count(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn test_retry_gives_up() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .retry_policy(fast_retries())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+
+    server.fail_next(10, StatusCode::SERVICE_UNAVAILABLE, None);
+    let accounts = client.get_accounts().await;
+    assert!(accounts.is_err());
+    // first attempt plus 3 retries
+    assert_eq!(server.requests().len(), 1 + 4);
+}
+
+#[tokio::test]
+async fn test_retry_honours_retry_after() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .retry_policy(fast_retries())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+
+    server.fail_next(1, StatusCode::TOO_MANY_REQUESTS, Some(1));
+    let start = std::time::Instant::now();
+    let accounts = client.get_accounts().await;
+    assert!(accounts.is_ok());
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_retry_after_over_cap_gives_up() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .retry_policy(fast_retries())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+
+    server.fail_next(1, StatusCode::TOO_MANY_REQUESTS, Some(86400));
+    let start = std::time::Instant::now();
+    let accounts = client.get_accounts().await;
+    assert!(
+        matches!(accounts, Err(Error::Api { status, .. }) if status == StatusCode::TOO_MANY_REQUESTS)
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_no_retry_for_payments() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .retry_policy(fast_retries())
+        .refresh_auth()
+        .build()
+        .unwrap();
+    let bens = client.get_beneficiaries().await.unwrap();
+    let payment = Payment::to(&bens.data[0].beneficiary_id)
+        .amount(1.0)
+        .my_reference("test me")
+        .their_reference("test them")
+        .build()
+        .unwrap();
+
+    server.fail_next(1, StatusCode::BAD_GATEWAY, None);
+    let resp = client.pay_single(SANDBOX_ACCOUNT, payment).await;
+    assert!(resp.is_err());
+    let payments = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.ends_with("/paymultiple"))
+        .count();
+    assert_eq!(payments, 1);
+}
+
+#[test]
+fn test_retry_backoff() {
+    let policy = RetryPolicy::new(5)
+        .initial_backoff(Duration::from_millis(100))
+        .max_backoff(Duration::from_millis(500))
+        .jitter(false);
+    assert_eq!(policy.backoff(0), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(400));
+    assert_eq!(policy.backoff(4), Duration::from_millis(500));
+
+    let policy = policy.jitter(true);
+    for retry in 0..5 {
+        let backoff = policy.backoff(retry);
+        assert!(backoff <= Duration::from_millis(500));
+        assert!(backoff >= Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_retry_backoff_bad_multiplier() {
+    for multiplier in [-2.0, 0.5, f64::NAN] {
+        let policy = RetryPolicy::new(3)
+            .initial_backoff(Duration::from_millis(100))
+            .multiplier(multiplier)
+            .jitter(false);
+        for retry in 0..3 {
+            assert_eq!(policy.backoff(retry), Duration::from_millis(100));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_get_account_transactions_page() {
+    let server = MockServer::with_fixtures(Fixtures {
+        page_size: 2,
+        ..Fixtures::default()
+    });
+    let client = server.client();
+
+    let first = client
+        .get_account_transactions(SANDBOX_ACCOUNT, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(first.meta.total_pages, 3);
+    assert_eq!(first.data.transactions.len(), 2);
+
+    let last = client
+        .get_account_transactions_page(SANDBOX_ACCOUNT, None, None, None, 3)
+        .await
+        .unwrap();
+    assert_eq!(last.data.transactions.len(), 1);
+}
+
+#[tokio::test]
+async fn test_account_transactions_stream() {
+    let server = MockServer::with_fixtures(Fixtures {
+        page_size: 2,
+        ..Fixtures::default()
+    });
+    let client = server.client();
+
+    let transactions: Vec<_> = client
+        .account_transactions(SANDBOX_ACCOUNT, None, None, None)
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(transactions.len(), server.fixtures().transactions.len());
+
+    let t_type = TransactionType::CardPurchases;
+    let transactions: Vec<_> = client
+        .account_transactions(SANDBOX_ACCOUNT, None, None, Some(t_type.clone()))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(transactions.len(), 3);
+    assert!(transactions.iter().all(|t| t.transaction_type == t_type));
+}
+
+#[tokio::test]
+async fn test_account_transactions_stream_error() {
+    let server = MockServer::start();
+    let client = server.client();
+    let mut transactions = Box::pin(client.account_transactions("unknown", None, None, None));
+    assert!(transactions.next().await.unwrap().is_err());
+    assert!(transactions.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_rate_limit_shared_by_clones() {
+    let server = MockServer::start();
+    let limit = RateLimit::new(1, Duration::from_millis(50));
+    let client = server
+        .builder()
+        .refresh_auth()
+        .rate_limit(EndpointGroup::AccountInfo, limit)
+        .build()
+        .unwrap();
+    client.authenticate().await.unwrap();
+
+    let started = std::time::Instant::now();
+    let handles: Vec<_> = (0..5)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.get_accounts().await })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.await.unwrap().is_ok());
+    }
+    // the first request uses the burst, the other four wait their turn
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_group() {
+    let server = MockServer::start();
+    let limit = RateLimit::new(1, Duration::from_secs(60));
+    let client = server
+        .builder()
+        .refresh_auth()
+        .rate_limit(EndpointGroup::Payments, limit)
+        .build()
+        .unwrap();
+
+    let started = std::time::Instant::now();
+    for _ in 0..3 {
+        assert!(client.get_accounts().await.is_ok());
+    }
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_rate_limit_group_with_host_path() {
+    let server = MockServer::start();
+    // the mock doesn't serve under a path, so requests fail, but they are
+    // still counted against the limit of their group
+    let host = Host::Custom(Url::parse(&format!("{}/investec", server.url())).unwrap());
+    let client = server
+        .builder()
+        .host(host)
+        .rate_limit(
+            EndpointGroup::Auth,
+            RateLimit::new(1, Duration::from_secs(60)),
+        )
+        .build()
+        .unwrap();
+    client.set_access_token(Some(AccessToken {
+        access_token: "token".to_string(),
+        token_type: "Bearer".to_string(),
+        scope: vec![],
+        expires_at: chrono::Utc::now() + chrono::Duration::minutes(30),
+        issued_for: None,
+    }));
+
+    assert!(client.get_access_token().await.is_err());
+    let started = std::time::Instant::now();
+    for _ in 0..3 {
+        assert!(client.get_accounts().await.is_err());
+    }
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let token = tokio::time::timeout(Duration::from_millis(500), client.get_access_token()).await;
+    assert!(token.is_err(), "second token request not limited");
+}
+
+#[tokio::test]
+async fn test_cache_slow_changing_endpoints() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .refresh_auth()
+        .cache(Cache::memory())
+        .build()
+        .unwrap();
+
+    for _ in 0..2 {
+        assert!(client.get_accounts().await.is_ok());
+        assert!(client.get_beneficiaries().await.is_ok());
+        assert!(client.get_account_balance(SANDBOX_ACCOUNT).await.is_ok());
+    }
+    let balance = format!("GET /za/pb/v1/accounts/{SANDBOX_ACCOUNT}/balance");
+    let requests = server.requests();
+    let count = |request: &str| requests.iter().filter(|r| *r == request).count();
+    assert_eq!(count("GET /za/pb/v1/accounts"), 1);
+    assert_eq!(count("GET /za/pb/v1/accounts/beneficiaries"), 1);
+    // balances aren't cached by default
+    assert_eq!(count(&balance), 2);
+
+    client.invalidate(&GetAccounts).unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    assert!(client.get_beneficiaries().await.is_ok());
+    client.clear_cache().unwrap();
+    assert!(client.get_beneficiaries().await.is_ok());
+    let requests = server.requests();
+    let count = |request: &str| requests.iter().filter(|r| *r == request).count();
+    assert_eq!(count("GET /za/pb/v1/accounts"), 2);
+    assert_eq!(count("GET /za/pb/v1/accounts/beneficiaries"), 2);
+}
+
+#[tokio::test]
+async fn test_cache_ttl() {
+    let server = MockServer::start();
+    let cache = Cache::memory()
+        .ttl("get_accounts", Duration::ZERO)
+        .ttl("get_account_transactions", Duration::from_millis(100));
+    let client = server
+        .builder()
+        .refresh_auth()
+        .cache(cache)
+        .build()
+        .unwrap();
+
+    let first_page = GetAccountTransactions {
+        account_id: SANDBOX_ACCOUNT.to_string(),
+        from_date: None,
+        to_date: None,
+        transaction_type: None,
+        page: Some(1),
+    };
+    let second_page = GetAccountTransactions {
+        account_id: SANDBOX_ACCOUNT.to_string(),
+        from_date: None,
+        to_date: None,
+        transaction_type: None,
+        page: Some(2),
+    };
+    let first = client.execute(&first_page).await.unwrap();
+    let cached = client.execute(&first_page).await.unwrap();
+    assert_eq!(
+        cached.data.transactions.len(),
+        first.data.transactions.len()
+    );
+    assert!(client.execute(&second_page).await.is_ok());
+    assert!(client.get_accounts().await.is_ok());
+    assert!(client.get_accounts().await.is_ok());
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(client.execute(&second_page).await.is_ok());
+
+    let transactions = format!("GET /za/pb/v1/accounts/{SANDBOX_ACCOUNT}/transactions");
+    let requests = server.requests();
+    let count = |request: &str| requests.iter().filter(|r| *r == request).count();
+    // each page once, and the second again after it expired
+    assert_eq!(count(&transactions), 3);
+    assert_eq!(count("GET /za/pb/v1/accounts"), 2);
+}
+
+#[tokio::test]
+async fn test_disk_cache() {
+    let server = MockServer::start();
+    let dir = server.token_path().with_extension("cache");
+    let build = || {
+        server
+            .builder()
+            .refresh_auth()
+            .cache(Cache::new(DiskCache::new(dir.clone())))
+            .build()
+            .unwrap()
+    };
+
+    let profiles = build().get_profiles().await.unwrap();
+    // a new client picks up what the first one cached
+    let cached = build().get_profiles().await.unwrap();
+    assert_eq!(cached.data.len(), profiles.data.len());
+    let requests = server.requests();
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| *r == "GET /za/pb/v1/profiles")
+            .count(),
+        1
+    );
+
+    build().clear_cache().unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_disk_cache_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::cache::{CacheBackend, CacheEntry};
+
+    let server = MockServer::start();
+    let dir = server.token_path().with_extension("private-cache");
+    let cache = DiskCache::new(dir.clone());
+    let entry = CacheEntry::new(b"{}".to_vec(), Duration::from_secs(60));
+    cache.set("key", entry).unwrap();
+
+    let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode();
+    assert_eq!(mode(&dir) & 0o777, 0o700);
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    // no temporary file left behind
+    assert_eq!(files.len(), 1);
+    assert_eq!(mode(&files[0]) & 0o777, 0o600);
+    assert_eq!(cache.get("key").unwrap().unwrap().body, b"{}");
+
+    cache.clear().unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_record_and_replay() {
+    let server = MockServer::start();
+    let recorder = Recorder::new();
+    let client = server
+        .builder()
+        .refresh_auth()
+        .middleware(recorder.clone())
+        .build()
+        .unwrap();
+    let from_date = NaiveDate::from_ymd_opt(2023, 10, 1);
+    let accounts = client.get_accounts().await.unwrap();
+    client.get_account_balance(SANDBOX_ACCOUNT).await.unwrap();
+    let transactions = client
+        .get_account_transactions(SANDBOX_ACCOUNT, from_date, None, None)
+        .await
+        .unwrap();
+
+    // asked for explicitly, so scrubbed however short
+    recorder.scrub("ZAR");
+    let path = server.token_path().with_extension("cassette.json");
+    recorder.save(&path).unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let token = client.access_token().unwrap().access_token;
+    for secret in [
+        SANDBOX_ACCOUNT,
+        SANDBOX_PROFILE,
+        "10011001001",
+        "ZAR",
+        &token,
+    ] {
+        assert!(!saved.contains(secret), "{secret} not scrubbed");
+    }
+
+    let replay = serde_json::from_str::<Cassette>(&saved).unwrap().serve();
+    let client = replay.client();
+    let replayed = client.get_accounts().await.unwrap();
+    assert_eq!(replayed.data.accounts.len(), accounts.data.accounts.len());
+    // ids in replayed responses lead to the matching recorded requests
+    let account_id = &replayed.data.accounts[0].account_id;
+    assert_ne!(account_id, SANDBOX_ACCOUNT);
+    assert!(client.get_account_balance(account_id).await.is_ok());
+    let replayed = client
+        .get_account_transactions(account_id, from_date, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        replayed.data.transactions.len(),
+        transactions.data.transactions.len()
+    );
+
+    // a different query is not in the cassette
+    let unmatched = client
+        .get_account_transactions(account_id, None, None, None)
+        .await;
+    assert!(
+        matches!(unmatched, Err(Error::Api { status, .. }) if status == StatusCode::NOT_IMPLEMENTED)
+    );
+    assert!(client.get_profiles().await.is_err());
+    assert_eq!(
+        replay.take_unmatched(),
+        [
+            format!("GET /za/pb/v1/accounts/{account_id}/transactions"),
+            "GET /za/pb/v1/profiles".to_string(),
+        ]
+    );
+}
+
+#[test]
+#[should_panic(expected = "requests not in the cassette")]
+fn test_replay_panics_on_unmatched() {
+    let replay = Cassette::default().serve();
+    let client = replay.client();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    assert!(runtime.block_on(client.get_access_token()).is_err());
+}
+
+#[tokio::test]
+async fn test_response_meta() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    let (accounts, meta) = ResponseMeta::capture(client.get_accounts()).await;
+    assert!(accounts.is_ok());
+    let meta = meta.unwrap();
+    assert_eq!(meta.status, StatusCode::OK);
+    // the token request came first
+    assert_eq!(meta.request_id.as_deref(), Some("mock-request-2"));
+    assert!(meta.body_len.is_some_and(|len| len > 0));
+    assert!(meta.headers.contains_key("content-type"));
+
+    let payment = Payment::to("unknown")
+        .amount(1.0)
+        .my_reference("test me")
+        .their_reference("test them")
+        .build()
+        .unwrap();
+    let (resp, meta) = ResponseMeta::capture(client.pay_single(SANDBOX_ACCOUNT, payment)).await;
+    assert!(resp.is_err());
+    let meta = meta.unwrap();
+    assert!(meta.status.is_client_error());
+    assert_eq!(meta.request_id.as_deref(), Some("mock-request-3"));
+
+    // nothing is captured outside of `capture`
+    let (_, meta) = ResponseMeta::capture(async {}).await;
+    assert!(meta.is_none());
+}
+
+struct CorrelationId;
+
+impl Middleware for CorrelationId {
+    fn on_request(&self, request: &mut reqwest::Request) -> Result<(), Error> {
+        let value = HeaderValue::from_static("test-correlation");
+        request.headers_mut().insert("x-correlation-id", value);
+        Ok(())
+    }
+}
+
+#[derive(Default, Clone)]
+struct Audit(Arc<Mutex<Vec<(String, StatusCode, String)>>>);
+
+impl Middleware for Audit {
+    fn on_response(
+        &self,
+        request: &reqwest::Request,
+        response: &reqwest::Response,
+        body: Option<&[u8]>,
+    ) {
+        if request.method() == reqwest::Method::POST {
+            let body = String::from_utf8_lossy(body.unwrap_or_default()).to_string();
+            let entry = (request.url().path().to_string(), response.status(), body);
+            self.0.lock().unwrap().push(entry);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_middleware_injects_headers() {
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .refresh_auth()
+        .middleware(CorrelationId)
+        .build()
+        .unwrap();
+    assert!(client.get_accounts().await.is_ok());
+    let values = server.header_values("x-correlation-id");
+    // token request and accounts request
+    assert_eq!(values.len(), 2);
+    assert!(values
+        .iter()
+        .all(|v| v.as_deref() == Some("test-correlation")));
+}
+
+#[tokio::test]
+async fn test_middleware_observes_responses() {
+    let server = MockServer::start();
+    let audit = Audit::default();
+    let client = server
+        .builder()
+        .refresh_auth()
+        .middleware(audit.clone())
+        .build()
+        .unwrap();
+    let bens = client.get_beneficiaries().await.unwrap();
+    let payment = Payment::to(&bens.data[0].beneficiary_id)
+        .amount(1.0)
+        .my_reference("test me")
+        .their_reference("test them")
+        .build()
+        .unwrap();
+    let resp = client
+        .pay_multiple(SANDBOX_ACCOUNT, MutliPaymentRequest::new(vec![payment]))
+        .await;
+    // the body was read by the middleware and is still there for the client
+    assert!(resp.is_ok());
+
+    let audit = audit.0.lock().unwrap();
+    assert_eq!(audit.len(), 2);
+    assert!(audit[0].0.ends_with("/oauth2/token"));
+    let (path, status, body) = &audit[1];
+    assert!(path.ends_with(&format!("/accounts/{SANDBOX_ACCOUNT}/paymultiple")));
+    assert_eq!(*status, StatusCode::OK);
+    assert!(body.contains("TransferResponses"));
+}
+
+#[tokio::test]
+async fn test_middleware_aborts_request() {
+    struct Deny;
+    impl Middleware for Deny {
+        fn on_request(&self, _: &mut reqwest::Request) -> Result<(), Error> {
+            Err(Error::CustomRequest("denied".to_string()))
+        }
+    }
+
+    let server = MockServer::start();
+    let client = server
+        .builder()
+        .refresh_auth()
+        .middleware(Deny)
+        .build()
+        .unwrap();
+    let resp = client.get_accounts().await;
+    assert!(matches!(resp, Err(Error::CustomRequest(msg)) if msg == "denied"));
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn test_from_env_missing_vars() {
+    std::env::remove_var("INVESTEC_CLIENT_ID");
+    std::env::remove_var("INVESTEC_CLIENT_SECRET");
+    std::env::remove_var("INVESTEC_API_KEY");
+    let client = Client::from_env().build();
+    assert!(matches!(client, Err(Error::ClientFieldUndefined { .. })));
+}
+
+#[cfg(feature = "config")]
+mod config {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{client::ClientBuilder, config::Config};
+
+    /// a directory with a config file for the server, removed on drop
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(server: &MockServer) -> Self {
+            let dir = server.token_path().with_extension("config");
+            std::fs::create_dir_all(&dir).unwrap();
+            let fixtures = server.fixtures();
+            let port = server.url().port().unwrap();
+            let config = format!(
+                r#"
+env_file = ".env"
+
+[profiles.household]
+host = "{url}"
+client_id = "{id}"
+client_secret_env = "TEST_{port}_SECRET"
+api_key_env = "TEST_{port}_API_KEY"
+token_file = "household.json"
+refresh_auth = true
+scopes = ["accounts", "balances"]
+timeout_secs = 10
+
+[profiles.household.retry]
+max_retries = 2
+initial_backoff_ms = 1
+
+[profiles.incomplete]
+host = "{url}"
+client_id = "{id}"
+"#,
+                url = server.url(),
+                id = fixtures.client_id,
+            );
+            std::fs::write(dir.join("investec.toml"), config).unwrap();
+            let env = format!(
+                "TEST_{port}_SECRET={}\nTEST_{port}_API_KEY={}\n",
+                fixtures.client_secret, fixtures.api_key
+            );
+            std::fs::write(dir.join(".env"), env).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self) -> PathBuf {
+            self.0.join("investec.toml")
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_config() {
+        let server = MockServer::start();
+        let dir = ConfigDir::new(&server);
+
+        let client = ClientBuilder::from_config(dir.path(), "household")
+            .unwrap()
+            .build()
+            .unwrap();
+        server.fail_next(1, StatusCode::BAD_GATEWAY, None);
+        assert!(client.get_accounts().await.is_ok());
+        assert!(dir.0.join("household.json").exists());
+
+        let incomplete = ClientBuilder::from_config(dir.path(), "incomplete")
+            .unwrap()
+            .build();
+        assert!(
+            matches!(incomplete, Err(Error::ClientFieldUndefined { field }) if field == "secret")
+        );
+        let unknown = ClientBuilder::from_config(dir.path(), "unknown");
+        assert!(matches!(unknown, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let server = MockServer::start();
+        let dir = ConfigDir::new(&server);
+        std::fs::write(dir.path(), "[profiles.x]\nclient_idd = \"typo\"\n").unwrap();
+        assert!(matches!(Config::load(dir.path()), Err(Error::Config(_))));
+        std::fs::write(
+            dir.path(),
+            "[profiles.x]\nscopes = [\"accounts\", \"nope\"]\n",
+        )
+        .unwrap();
+        let scopes = ClientBuilder::from_config(dir.path(), "x");
+        assert!(matches!(scopes, Err(Error::Config(message)) if message == "unknown scope nope"));
+
+        for retry in [
+            "multiplier = -2.0",
+            "multiplier = nan",
+            "initial_backoff_ms = 10\nmax_backoff_ms = 1",
+        ] {
+            std::fs::write(dir.path(), format!("[profiles.x.retry]\n{retry}\n")).unwrap();
+            let invalid = ClientBuilder::from_config(dir.path(), "x");
+            assert!(matches!(invalid, Err(Error::Config(_))), "{retry}");
+        }
+        let missing = Config::load(dir.0.join("missing.toml"));
+        assert!(matches!(missing, Err(Error::Io(_))));
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod encryption {
+    use super::*;
+    use crate::token::EncryptedFileStore;
+
+    fn token() -> AccessToken {
+        AccessToken {
+            access_token: "secret-token".to_string(),
+            token_type: "Bearer".to_string(),
+            scope: vec![],
+            expires_at: chrono::Utc::now() + chrono::Duration::minutes(30),
+            issued_for: None,
+        }
+    }
+
+    #[test]
+    fn test_encrypted_store() {
+        let server = MockServer::start();
+        let path = server.token_path();
+        let store = EncryptedFileStore::new(path.clone(), [7; 32]);
+        store.write(&token()).unwrap();
+
+        let body = std::fs::read_to_string(&path).unwrap();
+        assert!(!body.contains("secret-token"));
+        assert_eq!(store.read().unwrap().access_token, "secret-token");
+        assert!(EncryptedFileStore::new(path.clone(), [8; 32])
+            .read()
+            .is_err());
+        assert!(FileStore::new(path).read().is_err());
+    }
+
+    #[test]
+    fn test_encrypted_store_passphrase() {
+        let server = MockServer::start();
+        let path = server.token_path();
+        EncryptedFileStore::with_passphrase(path.clone(), "correct horse")
+            .write(&token())
+            .unwrap();
+
+        let store = EncryptedFileStore::with_passphrase(path.clone(), "correct horse");
+        assert_eq!(store.read().unwrap().access_token, "secret-token");
+        let wrong = EncryptedFileStore::with_passphrase(path, "battery staple");
+        assert!(wrong.read().is_err());
+    }
+
+    #[test]
+    fn test_encrypted_store_from_env() {
+        let server = MockServer::start();
+        let var = format!("TEST_{}_TOKEN_KEY", server.url().port().unwrap());
+        assert!(EncryptedFileStore::from_env(server.token_path(), &var).is_err());
+        std::env::set_var(&var, "not a key");
+        assert!(EncryptedFileStore::from_env(server.token_path(), &var).is_err());
+
+        std::env::set_var(&var, EncryptedFileStore::generate_key());
+        let store = EncryptedFileStore::from_env(server.token_path(), &var).unwrap();
+        store.write(&token()).unwrap();
+        let store = EncryptedFileStore::from_env(server.token_path(), &var).unwrap();
+        assert!(store.read().is_ok());
+        std::env::remove_var(&var);
+    }
+
+    #[tokio::test]
+    async fn test_client_with_encrypted_store() {
+        let server = MockServer::start();
+        let key = [1; 32];
+        let client = server
+            .builder()
+            .token_store(EncryptedFileStore::new(server.token_path(), key))
+            .refresh_auth()
+            .build()
+            .unwrap();
+        assert!(client.get_accounts().await.is_ok());
+
+        // a client with the same key reuses the token, one with another key replaces it
+        let client = server
+            .builder()
+            .token_store(EncryptedFileStore::new(server.token_path(), key))
+            .refresh_auth()
+            .build()
+            .unwrap();
+        assert!(client.get_accounts().await.is_ok());
+        assert_eq!(server.token_requests(), 1);
+        let client = server
+            .builder()
+            .token_store(EncryptedFileStore::new(server.token_path(), [2; 32]))
+            .refresh_auth()
+            .build()
+            .unwrap();
+        assert!(client.get_accounts().await.is_ok());
+        assert_eq!(server.token_requests(), 2);
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod tracing {
+    use std::collections::HashMap;
+
+    use ::tracing::{
+        field::{Field, Visit},
+        span, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    use super::*;
+
+    type SpanFields = (String, HashMap<String, String>);
+
+    /// keeps the fields of every span
+    #[derive(Default, Clone)]
+    struct Spans(Arc<Mutex<Vec<SpanFields>>>);
+
+    impl Spans {
+        /// fields of the spans of an endpoint
+        fn get(&self, endpoint: &str) -> Vec<HashMap<String, String>> {
+            let spans = self.0.lock().unwrap();
+            spans
+                .iter()
+                .filter(|(name, fields)| {
+                    name == "endpoint" && fields.get("endpoint").is_some_and(|e| e == endpoint)
+                })
+                .map(|(_, fields)| fields.clone())
+                .collect()
+        }
+    }
+
+    /// index of a span in `Spans`
+    struct Index(usize);
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{value:?}").trim_matches('"').to_string();
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((attrs.metadata().name().to_string(), fields));
+            let span = ctx.span(id).unwrap();
+            span.extensions_mut().insert(Index(spans.len() - 1));
+        }
+
+        fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            let extensions = span.extensions();
+            let Index(index) = extensions.get::<Index>().unwrap();
+            values.record(&mut Fields(&mut self.0.lock().unwrap()[*index].1));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_spans() {
+        let server = MockServer::start();
+        let client = server
+            .builder()
+            .retry_policy(fast_retries())
+            .refresh_auth()
+            .build()
+            .unwrap();
+        let spans = Spans::default();
+        let _guard = tracing_subscriber::registry()
+            .with(spans.clone())
+            .set_default();
+
+        assert!(client.get_account_balance(MOCK_ACCOUNT).await.is_ok());
+        server.fail_next(1, StatusCode::SERVICE_UNAVAILABLE, None);
+        assert!(client.get_account_balance(MOCK_ACCOUNT).await.is_ok());
+
+        let token = &spans.get("get_access_token")[0];
+        assert_eq!(token["method"], "POST");
+        assert_eq!(token["status"], "200");
+        let balance = spans.get("get_account_balance");
+        assert_eq!(balance[0]["method"], "GET");
+        assert_eq!(balance[0]["path"], "/za/pb/v1/accounts/{accountId}/balance");
+        assert_eq!(balance[0]["status"], "200");
+        assert_eq!(balance[0]["retries"], "0");
+        assert_eq!(balance[0]["token_refreshed"], "true");
+        assert!(balance[0].contains_key("latency_ms"));
+        assert_eq!(balance[1]["retries"], "1");
+        assert_eq!(balance[1]["token_refreshed"], "false");
+
+        // no account numbers or tokens anywhere
+        let token = client.access_token().unwrap().access_token;
+        for (_, fields) in spans.0.lock().unwrap().iter() {
+            for value in fields.values() {
+                assert!(!value.contains(MOCK_ACCOUNT));
+                assert!(!value.contains(&token));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transaction_pages_spans() {
+        let fixtures = Fixtures {
+            page_size: 2,
+            ..Fixtures::default()
+        };
+        let server = MockServer::with_fixtures(fixtures);
+        let client = server.client();
+        let spans = Spans::default();
+        let _guard = tracing_subscriber::registry()
+            .with(spans.clone())
+            .set_default();
+
+        let transactions: Vec<_> = client
+            .account_transactions(MOCK_ACCOUNT, None, None, None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(transactions.len(), 5);
+
+        let pages = spans.get("get_account_transactions");
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0]["token_refreshed"], "true");
+        assert_eq!(pages[1]["token_refreshed"], "false");
+    }
+}
+
+#[cfg(feature = "blocking")]
+mod blocking {
+    use super::*;
+    use crate::blocking::Client;
+
+    fn client(server: &MockServer) -> Client {
+        server
+            .builder()
+            .token_store(FileStore::new(server.token_path()))
+            .refresh_auth()
+            .build_blocking()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_blocking_account_info() {
+        let server = MockServer::start();
+        let client = client(&server);
+
+        let accounts = client.get_accounts().unwrap();
+        assert!(!accounts.data.accounts.is_empty());
+        assert!(client.get_account_balance(SANDBOX_ACCOUNT).is_ok());
+        assert!(client.get_profiles().is_ok());
+        assert!(client.get_profile_accounts(SANDBOX_PROFILE).is_ok());
+        assert!(client.get_beneficiary_categories().is_ok());
+
+        let transactions: Result<Vec<_>, _> = client
+            .account_transactions(SANDBOX_ACCOUNT, None, None, None)
+            .collect();
+        assert_eq!(
+            transactions.unwrap().len(),
+            server.fixtures().transactions.len()
+        );
+        assert_eq!(server.token_requests(), 1);
+    }
+
+    #[test]
+    fn test_blocking_response_meta() {
+        let server = MockServer::start();
+        let client = client(&server);
+
+        let (accounts, meta) = ResponseMeta::capture_blocking(|| client.get_accounts());
+        assert!(accounts.is_ok());
+        assert_eq!(meta.unwrap().request_id.as_deref(), Some("mock-request-2"));
+    }
+
+    #[test]
+    fn test_blocking_payments() {
+        let server = MockServer::start();
+        let client = client(&server);
+
+        let transfer = Transfer::to(SANDBOX_ACCOUNT)
+            .amount(10.0)
+            .my_reference("test")
+            .their_reference("test theirs")
+            .build()
+            .unwrap();
+        assert!(client
+            .transfer_single(SANDBOX_ACCOUNT, transfer, None)
+            .is_ok());
+
+        let bens = client.get_beneficiaries().unwrap();
+        let payment = Payment::to(&bens.data[0].beneficiary_id)
+            .amount(1.0)
+            .my_reference("test me")
+            .their_reference("test them")
+            .build()
+            .unwrap();
+        assert!(client.pay_single(SANDBOX_ACCOUNT, payment).is_ok());
+    }
+}
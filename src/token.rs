@@ -1,7 +1,20 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::client::Host;
+
 // Token as received from API
 #[derive(Deserialize, Serialize, Debug)]
 pub struct AccessTokenResponse {
@@ -19,12 +32,91 @@ pub struct AccessToken {
     pub token_type: String,
     pub scope: Vec<Scope>,
     pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// host and client the token was issued to, `None` if unknown
+    #[serde(default)]
+    pub issued_for: Option<TokenKey>,
 }
 
 impl AccessToken {
     pub fn expired(&self) -> bool {
         chrono::Utc::now() >= self.expires_at
     }
+
+    /// whether the token was granted `scope`. Tokens without any scopes, e.g.
+    /// built by hand, are assumed to have them all.
+    pub fn has_scope(&self, scope: &Scope) -> bool {
+        self.scope.is_empty() || self.scope.contains(scope)
+    }
+
+    /// whether the token expires in less than `leeway`, or already has
+    pub fn expires_within(&self, leeway: std::time::Duration) -> bool {
+        let leeway = chrono::Duration::from_std(leeway).unwrap_or(chrono::Duration::MAX);
+        chrono::Utc::now()
+            .checked_add_signed(leeway)
+            .is_none_or(|at| at >= self.expires_at)
+    }
+}
+
+/// What a token is valid for: the host that issued it, the client it was
+/// issued to and the scopes it was requested for. Clients ignore stored
+/// tokens issued for another key.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TokenKey {
+    /// base url of the host
+    pub host: String,
+    pub client_id: String,
+    /// scopes requested with [`crate::client::ClientBuilder::scopes`], sorted,
+    /// empty for all the client is allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<Scope>,
+}
+
+impl TokenKey {
+    pub fn new(host: &Host, client_id: impl Into<String>) -> Self {
+        Self {
+            host: host.url(),
+            client_id: client_id.into(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// the key of tokens requested for `scopes` only
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = Scope>) -> Self {
+        let mut scopes: Vec<_> = scopes.into_iter().collect();
+        scopes.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        scopes.dedup();
+        self.scopes = scopes;
+        self
+    }
+
+    /// the scopes space separated, as the token endpoint takes them
+    #[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+    fn scope_list(&self) -> String {
+        let scopes: Vec<_> = self.scopes.iter().map(Scope::as_str).collect();
+        scopes.join(" ")
+    }
+
+    /// host, client id and scopes with anything but letters, digits, `.` and
+    /// `-` replaced, to name files and storage entries
+    fn slug(&self) -> String {
+        let host = self
+            .host
+            .split_once("://")
+            .map_or(self.host.as_str(), |(_, host)| host);
+        let mut slug = format!("{host}_{}", self.client_id);
+        for scope in &self.scopes {
+            slug = format!("{slug}_{scope}");
+        }
+        slug.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
 }
 
 impl From<AccessTokenResponse> for AccessToken {
@@ -34,6 +126,7 @@ impl From<AccessTokenResponse> for AccessToken {
             token_type: value.token_type,
             scope: value.scope,
             expires_at: chrono::Utc::now() + chrono::Duration::seconds(value.expires_in as i64),
+            issued_for: None,
         }
     }
 }
@@ -44,14 +137,12 @@ where
     D: Deserializer<'de>,
 {
     let body: String = Deserialize::deserialize(deserializer)?;
-    let array = body
-        .split_whitespace()
-        .map(|o| Scope::from_str(o).unwrap())
-        .collect();
+    let array = body.split_whitespace().map(Scope::from).collect();
     Ok(array)
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Scope {
     Accounts,
     Balances,
@@ -61,48 +152,321 @@ pub enum Scope {
     Statements,
     Taxcertificates,
     Cards,
+    /// a scope this crate doesn't know yet
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl Scope {
+    /// the name used by the API, e.g. `beneficiarypayments`
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Accounts => "accounts",
+            Self::Balances => "balances",
+            Self::Transactions => "transactions",
+            Self::Transfers => "transfers",
+            Self::Beneficiarypayments => "beneficiarypayments",
+            Self::Statements => "statements",
+            Self::Taxcertificates => "taxcertificates",
+            Self::Cards => "cards",
+            Self::Unknown(scope) => scope,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Scope {
+    fn from(s: &str) -> Self {
+        match s {
+            "accounts" => Self::Accounts,
+            "balances" => Self::Balances,
+            "transactions" => Self::Transactions,
+            "transfers" => Self::Transfers,
+            "beneficiarypayments" => Self::Beneficiarypayments,
+            "statements" => Self::Statements,
+            "taxcertificates" => Self::Taxcertificates,
+            "cards" => Self::Cards,
+            _ => Self::Unknown(s.to_string()),
+        }
+    }
 }
 
 // TODO: is there a better way to deserialize this enum?
 impl FromStr for Scope {
-    type Err = ();
+    type Err = std::convert::Infallible;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "accounts" => Ok(Self::Accounts),
-            "balances" => Ok(Self::Balances),
-            "transactions" => Ok(Self::Transactions),
-            "transfers" => Ok(Self::Transfers),
-            "beneficiarypayments" => Ok(Self::Beneficiarypayments),
-            "statements" => Ok(Self::Statements),
-            "taxcertificates" => Ok(Self::Taxcertificates),
-            "cards" => Ok(Self::Cards),
-            _ => Err(()),
+        Ok(s.into())
+    }
+}
+
+/// Where a client keeps its access token, possibly shared with other
+/// processes, e.g. in Redis or Postgres.
+///
+/// Every [`TokenStore`] is an `AsyncTokenStore` too, its calls then run on the
+/// executor thread, so slow or remote backends should implement this trait.
+/// With both traits in scope, call a sync store as `TokenStore::read(&store)`.
+///
+/// ```
+/// use investec::token::{AccessToken, AsyncTokenStore, TokenStoreError};
+///
+/// struct RedisStore {/* connection pool */}
+///
+/// #[async_trait::async_trait]
+/// impl AsyncTokenStore for RedisStore {
+///     async fn read(&self) -> Result<Option<AccessToken>, TokenStoreError> {
+///         // GET investec:token, then serde_json::from_str
+///         Ok(None)
+///     }
+///
+///     async fn write(&self, token: &AccessToken) -> Result<(), TokenStoreError> {
+///         // SET investec:token with the token's expiry
+///         Ok(())
+///     }
+///
+///     async fn clear(&self) -> Result<(), TokenStoreError> {
+///         // DEL investec:token
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait AsyncTokenStore: Send + Sync {
+    /// the stored token, `None` if there is none
+    async fn read(&self) -> Result<Option<AccessToken>, TokenStoreError>;
+    async fn write(&self, token: &AccessToken) -> Result<(), TokenStoreError>;
+    /// forget the stored token, e.g. after the API rejected it
+    async fn clear(&self) -> Result<(), TokenStoreError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenStoreError {
+    #[error("Token store io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid stored token: {0}")]
+    Invalid(#[from] serde_json::Error),
+
+    /// any other failure of the backend, e.g. a lost connection
+    #[error("Token store error: {0}")]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl TokenStoreError {
+    pub fn backend(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self::Backend(error.into())
+    }
+}
+
+impl From<anyhow::Error> for TokenStoreError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<std::io::Error>() {
+            Ok(error) => return Self::Io(error),
+            Err(error) => error,
+        };
+        match error.downcast::<serde_json::Error>() {
+            Ok(error) => Self::Invalid(error),
+            Err(error) => Self::Backend(error.into()),
         }
     }
 }
 
-pub trait TokenStore {
+#[async_trait::async_trait]
+impl<T: TokenStore + ?Sized> AsyncTokenStore for T {
+    async fn read(&self) -> Result<Option<AccessToken>, TokenStoreError> {
+        match TokenStore::read(self).map_err(TokenStoreError::from) {
+            Ok(token) => Ok(Some(token)),
+            Err(TokenStoreError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn write(&self, token: &AccessToken) -> Result<(), TokenStoreError> {
+        Ok(TokenStore::write(self, token)?)
+    }
+
+    async fn clear(&self) -> Result<(), TokenStoreError> {
+        Ok(TokenStore::clear(self)?)
+    }
+}
+
+/// A store that reads and writes synchronously, like [`FileStore`]
+pub trait TokenStore: Send + Sync {
     fn read(&self) -> anyhow::Result<AccessToken>;
     fn write(&self, token: &AccessToken) -> anyhow::Result<()>;
+    /// forget the stored token, e.g. after the API rejected it
+    fn clear(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// the store used by `ClientBuilder::local_token`: a file in the cache
+/// directory natively, Web Storage in the browser, separate for every key
+pub(crate) fn local_store(key: &TokenKey) -> Box<dyn AsyncTokenStore> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let store = FileStore::for_key(key);
+    #[cfg(target_arch = "wasm32")]
+    let store = WebStorageStore::local(format!("investec_token_{}", key.slug()));
+    Box::new(store)
+}
+
+/// Anything [`crate::client::ClientBuilder::token_store`] accepts: every
+/// [`AsyncTokenStore`], and [`KeyedTokenStore`]s, which get scoped to the
+/// host and client id of the client being built.
+pub trait IntoTokenStore {
+    fn into_token_store(self, key: &TokenKey) -> Box<dyn AsyncTokenStore>;
+}
+
+impl<T: AsyncTokenStore + 'static> IntoTokenStore for T {
+    fn into_token_store(self, _key: &TokenKey) -> Box<dyn AsyncTokenStore> {
+        Box::new(self)
+    }
+}
+
+/// A store holding the tokens of many credential sets, by [`TokenKey`]
+#[async_trait::async_trait]
+pub trait KeyedTokenStore: Send + Sync {
+    async fn read(&self, key: &TokenKey) -> Result<Option<AccessToken>, TokenStoreError>;
+    async fn write(&self, key: &TokenKey, token: &AccessToken) -> Result<(), TokenStoreError>;
+    async fn clear(&self, key: &TokenKey) -> Result<(), TokenStoreError>;
+    /// keys of the tokens expiring before `at` with their expiry, soonest first
+    async fn expiring_before(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<(TokenKey, DateTime<Utc>)>, TokenStoreError>;
+    /// remove expired tokens, returning how many there were
+    async fn remove_expired(&self) -> Result<usize, TokenStoreError>;
+
+    /// the store of a single credential set
+    fn scoped(self, key: TokenKey) -> ScopedStore<Self>
+    where
+        Self: Sized,
+    {
+        ScopedStore { store: self, key }
+    }
+}
+
+/// A [`KeyedTokenStore`] limited to one key
+pub struct ScopedStore<S> {
+    pub store: S,
+    pub key: TokenKey,
 }
 
+#[async_trait::async_trait]
+impl<S: KeyedTokenStore> AsyncTokenStore for ScopedStore<S> {
+    async fn read(&self) -> Result<Option<AccessToken>, TokenStoreError> {
+        self.store.read(&self.key).await
+    }
+
+    async fn write(&self, token: &AccessToken) -> Result<(), TokenStoreError> {
+        self.store.write(&self.key, token).await
+    }
+
+    async fn clear(&self) -> Result<(), TokenStoreError> {
+        self.store.clear(&self.key).await
+    }
+}
+
+/// Tokens kept in memory, shared by all clones.
+///
+/// Give every client of a process, or every test, a clone: each client only
+/// sees the token of its own host and client id.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    tokens: Arc<Mutex<HashMap<TokenKey, AccessToken>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyedTokenStore for MemoryStore {
+    async fn read(&self, key: &TokenKey) -> Result<Option<AccessToken>, TokenStoreError> {
+        Ok(self.tokens.lock().unwrap().get(key).cloned())
+    }
+
+    async fn write(&self, key: &TokenKey, token: &AccessToken) -> Result<(), TokenStoreError> {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(key.clone(), token.clone());
+        Ok(())
+    }
+
+    async fn clear(&self, key: &TokenKey) -> Result<(), TokenStoreError> {
+        self.tokens.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn expiring_before(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<(TokenKey, DateTime<Utc>)>, TokenStoreError> {
+        let tokens = self.tokens.lock().unwrap();
+        let mut expiring: Vec<_> = tokens
+            .iter()
+            .filter(|(_, token)| token.expires_at < at)
+            .map(|(key, token)| (key.clone(), token.expires_at))
+            .collect();
+        expiring.sort_by_key(|(_, expires_at)| *expires_at);
+        Ok(expiring)
+    }
+
+    async fn remove_expired(&self) -> Result<usize, TokenStoreError> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let before = tokens.len();
+        tokens.retain(|_, token| !token.expired());
+        Ok(before - tokens.len())
+    }
+}
+
+impl IntoTokenStore for MemoryStore {
+    fn into_token_store(self, key: &TokenKey) -> Box<dyn AsyncTokenStore> {
+        Box::new(self.scoped(key.clone()))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 pub struct FileStore {
     pub path: PathBuf,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl FileStore {
     pub fn new(path: PathBuf) -> Self {
         Self { path }
     }
+
+    /// a file of its own for the host and client in [`FileStore::dir`]
+    pub fn for_key(key: &TokenKey) -> Self {
+        Self::new(Self::dir().join(format!("{}.json", key.slug())))
+    }
+
+    /// `investec/tokens` in the user's cache directory, e.g. `$XDG_CACHE_HOME`
+    /// or `~/.cache` on Linux, or the current directory if there is none
+    pub fn dir() -> PathBuf {
+        dirs::cache_dir()
+            .map(|dir| dir.join("investec").join("tokens"))
+            .unwrap_or_default()
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Default for FileStore {
+    /// `token.json` in [`FileStore::dir`], shared by all hosts and clients.
+    /// Prefer [`FileStore::for_key`].
     fn default() -> Self {
-        Self::new(PathBuf::from("token.json"))
+        Self::new(Self::dir().join("token.json"))
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl TokenStore for FileStore {
     fn read(&self) -> anyhow::Result<AccessToken> {
         let body = std::fs::read_to_string(&self.path)?;
@@ -112,7 +476,475 @@ impl TokenStore for FileStore {
 
     fn write(&self, token: &AccessToken) -> anyhow::Result<()> {
         let body = serde_json::to_string_pretty(token)?;
-        std::fs::write(&self.path, body)?;
+        write_private(&self.path, body.as_bytes())?;
         Ok(())
     }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Write a file only its owner can read, in a directory only its owner can
+/// enter, via a temporary file renamed into place so readers never see half
+/// a token or cache entry.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn write_private(path: &Path, body: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options.open(&tmp).and_then(|mut file| {
+        file.write_all(body)?;
+        file.sync_all()
+    });
+    let renamed = written.and_then(|_| std::fs::rename(&tmp, path));
+    if renamed.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    renamed
+}
+
+/// A [`FileStore`] that encrypts the token with XChaCha20-Poly1305.
+///
+/// The key is either 32 random bytes, e.g. from [`EncryptedFileStore::from_env`],
+/// or derived from a passphrase with Argon2id and a random salt kept in the
+/// file. A file that can't be decrypted reads as no token, and is replaced on
+/// the next write.
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+pub struct EncryptedFileStore {
+    pub path: PathBuf,
+    secret: Secret,
+    // key derived from the passphrase and the salt it was derived with,
+    // deriving is deliberately slow
+    derived: std::sync::Mutex<Option<(Vec<u8>, chacha20poly1305::Key)>>,
+}
+
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+enum Secret {
+    Key(chacha20poly1305::Key),
+    Passphrase(String),
+}
+
+/// what an [`EncryptedFileStore`] writes, binary fields base64 encoded
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+#[derive(Deserialize, Serialize)]
+struct Encrypted {
+    version: u8,
+    /// Argon2id salt, for keys derived from a passphrase
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+impl EncryptedFileStore {
+    const VERSION: u8 = 1;
+
+    pub fn new(path: PathBuf, key: [u8; 32]) -> Self {
+        Self {
+            path,
+            secret: Secret::Key(key.into()),
+            derived: Default::default(),
+        }
+    }
+
+    pub fn with_passphrase(path: PathBuf, passphrase: impl Into<String>) -> Self {
+        Self {
+            path,
+            secret: Secret::Passphrase(passphrase.into()),
+            derived: Default::default(),
+        }
+    }
+
+    /// use the base64 encoded 32 byte key in the environment variable `var`,
+    /// see [`EncryptedFileStore::generate_key`]
+    pub fn from_env(path: PathBuf, var: &str) -> anyhow::Result<Self> {
+        use base64::Engine;
+
+        let value = std::env::var(var).map_err(|e| anyhow::anyhow!("{var}: {e}"))?;
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| anyhow::anyhow!("{var} is not a base64 encoded 32 byte key"))?;
+        Ok(Self::new(path, key))
+    }
+
+    /// a new random key, base64 encoded for [`EncryptedFileStore::from_env`]
+    pub fn generate_key() -> String {
+        use base64::Engine;
+        use chacha20poly1305::{aead::OsRng, KeyInit, XChaCha20Poly1305};
+
+        base64::engine::general_purpose::STANDARD.encode(XChaCha20Poly1305::generate_key(OsRng))
+    }
+
+    /// the key for `salt`, a new salt if `None` and the key is derived
+    fn key(
+        &self,
+        salt: Option<Vec<u8>>,
+    ) -> anyhow::Result<(Option<Vec<u8>>, chacha20poly1305::Key)> {
+        use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+
+        let passphrase = match &self.secret {
+            Secret::Key(key) => return Ok((None, *key)),
+            Secret::Passphrase(passphrase) => passphrase,
+        };
+        let mut derived = self.derived.lock().unwrap();
+        if let Some((cached_salt, key)) = derived.as_ref() {
+            if salt.as_ref().is_none_or(|salt| salt == cached_salt) {
+                return Ok((Some(cached_salt.clone()), *key));
+            }
+        }
+        let salt = salt.unwrap_or_else(|| {
+            let mut salt = vec![0; 16];
+            OsRng.fill_bytes(&mut salt);
+            salt
+        });
+        let mut key = chacha20poly1305::Key::default();
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("deriving token key: {e}"))?;
+        *derived = Some((salt.clone(), key));
+        Ok((Some(salt), key))
+    }
+}
+
+#[cfg(all(feature = "encryption", not(target_arch = "wasm32")))]
+impl TokenStore for EncryptedFileStore {
+    fn read(&self) -> anyhow::Result<AccessToken> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use chacha20poly1305::{
+            aead::{Aead, Payload},
+            KeyInit, XChaCha20Poly1305, XNonce,
+        };
+
+        let body = std::fs::read_to_string(&self.path)?;
+        let encrypted: Encrypted = serde_json::from_str(&body)?;
+        anyhow::ensure!(
+            encrypted.version == Self::VERSION,
+            "unknown token file version {}",
+            encrypted.version
+        );
+        let salt = encrypted
+            .salt
+            .map(|salt| STANDARD.decode(salt))
+            .transpose()?;
+        let nonce = STANDARD.decode(encrypted.nonce)?;
+        anyhow::ensure!(nonce.len() == 24, "invalid nonce in token file");
+        let ciphertext = STANDARD.decode(encrypted.ciphertext)?;
+
+        let (_, key) = self.key(salt)?;
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: &[Self::VERSION],
+        };
+        let body = XChaCha20Poly1305::new(&key)
+            .decrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow::anyhow!("can't decrypt {}, wrong key?", self.path.display()))?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn write(&self, token: &AccessToken) -> anyhow::Result<()> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use chacha20poly1305::{
+            aead::{Aead, AeadCore, OsRng, Payload},
+            KeyInit, XChaCha20Poly1305,
+        };
+
+        let (salt, key) = self.key(None)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let body = serde_json::to_vec(token)?;
+        let payload = Payload {
+            msg: &body,
+            aad: &[Self::VERSION],
+        };
+        let ciphertext = XChaCha20Poly1305::new(&key)
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow::anyhow!("can't encrypt token"))?;
+        let encrypted = Encrypted {
+            version: Self::VERSION,
+            salt: salt.map(|salt| STANDARD.encode(salt)),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        write_private(&self.path, &serde_json::to_vec_pretty(&encrypted)?)?;
+        Ok(())
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Tokens in a SQLite database, e.g. shared by processes on one machine.
+///
+/// Clones share the connection. Queries run on tokio's blocking threads.
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+impl SqliteStore {
+    /// open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TokenStoreError> {
+        let connection = rusqlite::Connection::open(path).map_err(TokenStoreError::backend)?;
+        Self::new(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self, TokenStoreError> {
+        let connection =
+            rusqlite::Connection::open_in_memory().map_err(TokenStoreError::backend)?;
+        Self::new(connection)
+    }
+
+    /// use an open connection, creating the `investec_tokens` table if needed
+    pub fn new(connection: rusqlite::Connection) -> Result<Self, TokenStoreError> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS investec_tokens (
+                    host TEXT NOT NULL,
+                    client_id TEXT NOT NULL,
+                    token TEXT NOT NULL,
+                    expires_at INTEGER NOT NULL,
+                    scopes TEXT NOT NULL DEFAULT '',
+                    PRIMARY KEY (host, client_id, scopes)
+                );",
+            )
+            .map_err(TokenStoreError::backend)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, TokenStoreError> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().unwrap()))
+            .await
+            .map_err(TokenStoreError::backend)?
+            .map_err(TokenStoreError::backend)
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+#[async_trait::async_trait]
+impl KeyedTokenStore for SqliteStore {
+    async fn read(&self, key: &TokenKey) -> Result<Option<AccessToken>, TokenStoreError> {
+        use rusqlite::OptionalExtension;
+
+        let key = key.clone();
+        let token: Option<String> = self
+            .run(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT token FROM investec_tokens
+                         WHERE host = ?1 AND client_id = ?2 AND scopes = ?3",
+                        (&key.host, &key.client_id, key.scope_list()),
+                        |row| row.get(0),
+                    )
+                    .optional()
+            })
+            .await?;
+        Ok(token
+            .map(|token| serde_json::from_str(&token))
+            .transpose()?)
+    }
+
+    async fn write(&self, key: &TokenKey, token: &AccessToken) -> Result<(), TokenStoreError> {
+        let key = key.clone();
+        let body = serde_json::to_string(token)?;
+        let expires_at = token.expires_at.timestamp();
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO investec_tokens
+                 (host, client_id, scopes, token, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    &key.host,
+                    &key.client_id,
+                    key.scope_list(),
+                    &body,
+                    expires_at,
+                ),
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn clear(&self, key: &TokenKey) -> Result<(), TokenStoreError> {
+        let key = key.clone();
+        self.run(move |connection| {
+            connection.execute(
+                "DELETE FROM investec_tokens WHERE host = ?1 AND client_id = ?2 AND scopes = ?3",
+                (&key.host, &key.client_id, key.scope_list()),
+            )
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn expiring_before(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<(TokenKey, DateTime<Utc>)>, TokenStoreError> {
+        let at = at.timestamp();
+        let rows: Vec<(String, String, String, i64)> = self
+            .run(move |connection| {
+                let mut statement = connection.prepare(
+                    "SELECT host, client_id, scopes, expires_at FROM investec_tokens
+                     WHERE expires_at < ?1 ORDER BY expires_at",
+                )?;
+                let rows = statement.query_map([at], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?;
+                rows.collect()
+            })
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(host, client_id, scopes, expires_at)| {
+                let expires_at = DateTime::from_timestamp(expires_at, 0).unwrap_or_default();
+                let key = TokenKey {
+                    host,
+                    client_id,
+                    scopes: scopes.split_whitespace().map(Scope::from).collect(),
+                };
+                (key, expires_at)
+            })
+            .collect())
+    }
+
+    async fn remove_expired(&self) -> Result<usize, TokenStoreError> {
+        let now = Utc::now().timestamp();
+        self.run(move |connection| {
+            connection.execute("DELETE FROM investec_tokens WHERE expires_at <= ?1", [now])
+        })
+        .await
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+impl IntoTokenStore for SqliteStore {
+    fn into_token_store(self, key: &TokenKey) -> Box<dyn AsyncTokenStore> {
+        Box::new(self.scoped(key.clone()))
+    }
+}
+
+/// Keeps the token in the browser's Web Storage under `key`.
+///
+/// `localStorage` survives reloads and is shared by tabs of the same origin,
+/// `sessionStorage` only lives as long as the tab. Both are readable by any
+/// script running on the page.
+#[cfg(target_arch = "wasm32")]
+pub struct WebStorageStore {
+    pub key: String,
+    pub session: bool,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl WebStorageStore {
+    pub fn local(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            session: false,
+        }
+    }
+
+    pub fn session(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            session: true,
+        }
+    }
+
+    // looked up on every call: `web_sys::Storage` can't be shared between threads
+    fn storage(&self) -> anyhow::Result<web_sys::Storage> {
+        let window = web_sys::window().ok_or_else(|| anyhow::anyhow!("no window"))?;
+        let storage = if self.session {
+            window.session_storage()
+        } else {
+            window.local_storage()
+        };
+        storage
+            .map_err(js_error)?
+            .ok_or_else(|| anyhow::anyhow!("web storage not available"))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for WebStorageStore {
+    fn default() -> Self {
+        Self::local("investec_token")
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl TokenStore for WebStorageStore {
+    fn read(&self) -> anyhow::Result<AccessToken> {
+        let body = self
+            .storage()?
+            .get_item(&self.key)
+            .map_err(js_error)?
+            // not found, so it reads as no token through `AsyncTokenStore`
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no token stored under {}", self.key),
+                )
+            })?;
+        let token = serde_json::from_str(&body)?;
+        Ok(token)
+    }
+
+    fn write(&self, token: &AccessToken) -> anyhow::Result<()> {
+        let body = serde_json::to_string(token)?;
+        self.storage()?
+            .set_item(&self.key, &body)
+            .map_err(js_error)?;
+        Ok(())
+    }
+
+    fn clear(&self) -> anyhow::Result<()> {
+        self.storage()?.remove_item(&self.key).map_err(js_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn js_error(value: wasm_bindgen::JsValue) -> anyhow::Error {
+    anyhow::anyhow!("{value:?}")
 }
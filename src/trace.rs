@@ -0,0 +1,115 @@
+//! Endpoint spans of the `tracing` feature.
+//!
+//! Every endpoint call runs in an `endpoint` span recording only the method and
+//! the path template, so account numbers, IDs and tokens never end up in traces.
+//! The `record_*` functions fill in the remaining fields of the current span.
+//! Background token refreshes, which run outside any endpoint span, report
+//! failures as events.
+//! Everything here compiles to nothing without the feature.
+
+use std::future::Future;
+
+use reqwest::{Method, StatusCode};
+
+use crate::Error;
+
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(
+    future: F,
+    endpoint: &'static str,
+    method: &Method,
+    path: &'static str,
+) -> impl Future<Output = F::Output> {
+    use tracing::{field::Empty, Instrument};
+
+    let span = tracing::info_span!(
+        "endpoint",
+        endpoint,
+        method = %method,
+        path,
+        status = Empty,
+        latency_ms = Empty,
+        retries = 0,
+        token_refreshed = false,
+        cached = false,
+    );
+    future.instrument(span)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(
+    future: F,
+    _endpoint: &'static str,
+    _method: &Method,
+    _path: &'static str,
+) -> F {
+    future
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn record_status(status: StatusCode) {
+    tracing::Span::current().record("status", status.as_u16());
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn record_retries(retries: u32) {
+    tracing::Span::current().record("retries", retries);
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn record_token_refresh() {
+    tracing::Span::current().record("token_refreshed", true);
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn record_cache_hit() {
+    tracing::Span::current().record("cached", true);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_status(_status: StatusCode) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_retries(_retries: u32) {}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_token_refresh() {}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_cache_hit() {}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn token_refresh_failed(error: &Error) {
+    tracing::warn!(error = %error, "background token refresh failed");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn token_refresh_failed(_error: &Error) {}
+
+/// records the time until it is dropped as `latency_ms` of the current span
+pub(crate) struct Latency {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    // `Instant` isn't available in the browser
+    #[cfg(feature = "tracing")]
+    started: chrono::DateTime<chrono::Utc>,
+}
+
+impl Latency {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+            #[cfg(feature = "tracing")]
+            started: chrono::Utc::now(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Latency {
+    fn drop(&mut self) {
+        let elapsed = chrono::Utc::now() - self.started;
+        self.span.record("latency_ms", elapsed.num_milliseconds());
+    }
+}
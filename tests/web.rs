@@ -0,0 +1,110 @@
+//! Browser tests, run with `wasm-pack test --headless --firefox`.
+//! `fetch` is replaced by a stub so no network is needed.
+#![cfg(target_arch = "wasm32")]
+
+use investec::{
+    client::{ClientBuilder, Host, Url},
+    token::{TokenStore, WebStorageStore},
+};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen(inline_js = r#"
+export function install_mock_fetch() {
+    globalThis.__investecRequests = [];
+    globalThis.fetch = async (input, init) => {
+        const request = new Request(input, init);
+        const url = new URL(request.url);
+        globalThis.__investecRequests.push(`${request.method} ${url.pathname}`);
+        const json = (body, status = 200) =>
+            new Response(JSON.stringify(body), {
+                status,
+                headers: { "content-type": "application/json" },
+            });
+
+        if (url.pathname === "/identity/v2/oauth2/token") {
+            return json({
+                access_token: "web-token",
+                token_type: "Bearer",
+                expires_in: 1799,
+                scope: "accounts balances transactions",
+            });
+        }
+        if (request.headers.get("authorization") !== "Bearer web-token") {
+            return json({ message: "invalid bearer token" }, 401);
+        }
+        if (url.pathname === "/za/pb/v1/accounts") {
+            return json({
+                data: {
+                    accounts: [{
+                        accountId: "3353431574710163189587446",
+                        accountNumber: "10011001001",
+                        accountName: "Mr J Soap",
+                        referenceName: "Mr J Soap",
+                        productName: "Private Bank Account",
+                        kycCompliant: true,
+                        profileId: "10163189587444",
+                        profileName: "Joe Soap",
+                    }],
+                },
+                links: { self: url.href },
+                meta: { totalPages: 1 },
+            });
+        }
+        return json({ message: "not found" }, 404);
+    };
+}
+
+export function requests() {
+    return globalThis.__investecRequests.length;
+}
+"#)]
+extern "C" {
+    fn install_mock_fetch();
+    fn requests() -> u32;
+}
+
+fn client(store: WebStorageStore) -> investec::client::Client {
+    ClientBuilder::new()
+        .id("id".to_string())
+        .secret("secret".to_string())
+        .key("key".to_string())
+        .host(Host::Custom(Url::parse("https://investec.test").unwrap()))
+        .token_store(store)
+        .refresh_auth()
+        .build()
+        .unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn test_get_accounts() {
+    install_mock_fetch();
+    let store = WebStorageStore::session("investec_test_accounts");
+    store.clear().unwrap();
+
+    let client = client(store);
+    let accounts = client.get_accounts().await.unwrap();
+    assert_eq!(accounts.data.accounts.len(), 1);
+    assert_eq!(requests(), 2);
+}
+
+#[wasm_bindgen_test]
+async fn test_web_storage_token_is_reused() {
+    install_mock_fetch();
+    let key = "investec_test_reuse";
+    WebStorageStore::session(key).clear().unwrap();
+
+    client(WebStorageStore::session(key))
+        .authenticate()
+        .await
+        .unwrap();
+    let stored = WebStorageStore::session(key).read().unwrap();
+    assert_eq!(stored.access_token, "web-token");
+
+    // a second client picks the token up from storage instead of fetching one
+    let client = client(WebStorageStore::session(key));
+    client.get_accounts().await.unwrap();
+    assert_eq!(requests(), 2);
+}